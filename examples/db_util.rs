@@ -1,6 +1,15 @@
-use botshop_v2::util::db::{insert_user, query_user, update_user, User};
-use chrono::Utc;
-use clap::{ArgEnum, Parser, Subcommand};
+use std::path::PathBuf;
+
+use botshop_v2::util::{
+    backup,
+    catalog::Catalog,
+    db::{self, Database},
+    events,
+    records::parse_tags,
+    Origin, Priority, Record, Recurrence, Source, Surface, Task, Transaction,
+};
+use chrono::NaiveDateTime;
+use clap::{ArgEnum, Args, Parser, Subcommand};
 #[derive(Parser)]
 #[clap(name = "DB Util")]
 #[clap(author = "Xetera Mnemonics <grostaco@gmail.com>")]
@@ -42,6 +51,48 @@ enum Commands {
         #[clap(subcommand)]
         commands: ModifySub,
     },
+
+    /// Show every change ever made to a user's records
+    History {
+        /// ID of the user whose history to show
+        user_id: u64,
+    },
+
+    /// Take a snapshot of the database, keeping only the newest ones
+    Backup {
+        /// Directory to keep the snapshots in
+        #[clap(long, default_value = "resources/backups")]
+        dir: PathBuf,
+        /// How many snapshots to keep
+        #[clap(long, default_value_t = 7)]
+        keep: usize,
+    },
+
+    /// List the snapshots in a directory, oldest first
+    Snapshots {
+        /// Directory the snapshots are kept in
+        #[clap(long, default_value = "resources/backups")]
+        dir: PathBuf,
+    },
+
+    /// Replace the database with a snapshot
+    Restore {
+        /// Snapshot to restore
+        snapshot: PathBuf,
+    },
+
+    /// Rebuild a user's records from their history and compare them with the stored ones
+    Replay {
+        /// ID of the user to replay
+        user_id: u64,
+    },
+
+    /// Check a shop catalog and list its items
+    Catalog {
+        /// Catalog to check, a .toml, .json or .csv file
+        #[clap(default_value = "resources/shop.toml")]
+        path: PathBuf,
+    },
 }
 
 #[derive(Subcommand)]
@@ -54,27 +105,86 @@ enum ModifySub {
         points: i64,
         /// Record's timestamp to be inserted. Blank if it's not completed.
         timestamp: Option<i64>,
+        #[clap(flatten)]
+        options: RecordOptions,
     },
 
     /// Delete a record from a record type
     Delete {
-        /// Index of the record to delete
-        index: usize,
+        /// ID of the record to delete
+        id: i64,
     },
 
     /// Update an existing record in a record type
     Update {
-        /// Index of the record to update
-        index: usize,
+        /// ID of the record to update. A new record is inserted if it doesn't exist.
+        id: i64,
         /// Record's new name
         name: String,
         /// Record's new points
         points: i64,
-        /// Record's new timestamp. Blank if it's not completed.
+        /// Record's new timestamp. Kept as it was if blank.
         timestamp: Option<i64>,
+        /// How often a daily task is due. Kept as it was if blank.
+        #[clap(long)]
+        recurrence: Option<Recurrence>,
+        /// Timestamp a pending task is due by. Kept as it was if blank.
+        #[clap(long)]
+        due: Option<i64>,
+        /// Remove the due date of a pending task
+        #[clap(long, conflicts_with = "due")]
+        no_due: bool,
+        /// How much there is to do. Kept as it was if blank, 0 removes it.
+        #[clap(long)]
+        target: Option<u32>,
+        /// Whether to pay points as progress is made. Kept as it was if blank.
+        #[clap(long)]
+        partial: Option<bool>,
+        /// A step of a pending task's checklist, repeated for every step in order. Steps with
+        /// the same name stay checked. Kept as it was if blank.
+        #[clap(long = "item")]
+        items: Vec<String>,
+        /// A tag such as health or chores, repeated for every tag. Kept as they were if blank.
+        #[clap(long = "tag")]
+        tags: Vec<String>,
+        /// Points taken for every day a daily task is missed. Kept as it was if blank.
+        #[clap(long)]
+        penalty: Option<i64>,
+        /// low, normal, high or urgent. Kept as it was if blank.
+        #[clap(long)]
+        priority: Option<Priority>,
     },
 }
 
+/// Everything about a new record besides its name, points and timestamp
+#[derive(Args)]
+struct RecordOptions {
+    /// How often a daily task is due, e.g. "mon,wed,fri" or "every 3 days"
+    #[clap(long)]
+    recurrence: Option<Recurrence>,
+    /// Timestamp a pending task is due by
+    #[clap(long)]
+    due: Option<i64>,
+    /// How much there is to do, e.g. 8 for 8 glasses of water
+    #[clap(long)]
+    target: Option<u32>,
+    /// Pay points as progress is made instead of on reaching the target
+    #[clap(long)]
+    partial: bool,
+    /// A step of a pending task's checklist, repeated for every step in order
+    #[clap(long = "item")]
+    items: Vec<String>,
+    /// A tag such as health or chores, repeated for every tag
+    #[clap(long = "tag")]
+    tags: Vec<String>,
+    /// Points taken for every day a daily task is missed
+    #[clap(long, default_value_t = 0)]
+    penalty: i64,
+    /// low, normal, high or urgent
+    #[clap(long, default_value_t = Priority::Normal)]
+    priority: Priority,
+}
+
 #[derive(Copy, Clone, ArgEnum)]
 enum RecordType {
     Daily,
@@ -82,13 +192,54 @@ enum RecordType {
     Transaction,
 }
 
+/// A new record of the given type. Transactions without a timestamp happen now.
+fn new_record(
+    record_type: db::RecordType,
+    name: &str,
+    points: i64,
+    timestamp: Option<i64>,
+    options: &RecordOptions,
+) -> Record {
+    let mut record = match record_type.task_kind() {
+        Some(kind) => {
+            let mut task = Task::new(kind, name, points);
+            task.completed_at = timestamp;
+            task.recurrence = options.recurrence.clone().unwrap_or_default();
+            task.due_at = options.due;
+            task.target = options.target.filter(|target| *target > 0);
+            task.partial = options.partial;
+            task.set_checklist(options.items.clone());
+            task.penalty = options.penalty.max(0);
+            task.priority = options.priority;
+            Record::Task(task)
+        }
+        None => {
+            let mut transaction = Transaction::new(name, points, Source::Manual);
+            if let Some(timestamp) = timestamp {
+                transaction.timestamp = timestamp;
+            }
+            Record::Transaction(transaction)
+        }
+    };
+    record.set_tags(parse_tags(&options.tags.join(",")));
+    record
+}
+
 fn main() {
     let cli = Cli::parse();
 
+    let db = Database::open(&cli.dbfile).expect("Cannot open the database");
+    let (from, to) = db.migrate().expect("Cannot migrate the database");
+    if from != to {
+        println!(
+            "Migrated the database from schema version {} to {}",
+            from, to
+        );
+    }
+
     match &cli.command {
         Commands::Query { userid } => {
-            match query_user(
-                &cli.dbfile,
+            match db.query_user(
                 *userid,
             )
             .expect("Cannot query user")
@@ -101,7 +252,8 @@ fn main() {
             };
         }
         Commands::Add { userid } => {
-            insert_user(&cli.dbfile, User::new(*userid)).expect("Cannot add user to the database");
+            db.insert_user(*userid)
+                .expect("Cannot add user to the database");
         }
 
         Commands::Modify {
@@ -109,51 +261,211 @@ fn main() {
             record_type,
             commands,
         } => {
-            let mut user = query_user(&cli.dbfile, *user_id)
+            db.query_user(*user_id)
                 .expect("Cannot query user")
                 .expect("Cannot find specified user under the given ID");
 
-            let record = match record_type {
-                RecordType::Daily => &mut user.daily,
-                RecordType::Pending => &mut user.pending,
-                RecordType::Transaction => &mut user.transactions,
+            let record_type = match record_type {
+                RecordType::Daily => db::RecordType::Daily,
+                RecordType::Pending => db::RecordType::Pending,
+                RecordType::Transaction => db::RecordType::Transaction,
             };
 
-            match commands {
-                ModifySub::Update {
-                    index,
-                    name,
-                    points,
-                    timestamp,
-                } => {
-                    let mut timestamp = *timestamp;
-                    if matches!(record_type, RecordType::Transaction) && timestamp.is_none() {
-                        timestamp = Some(Utc::now().timestamp())
+            db.with_user_mut(*user_id, Origin::system(Surface::DbUtil), |user| {
+                match commands {
+                    ModifySub::Update {
+                        id,
+                        name,
+                        points,
+                        timestamp,
+                        recurrence,
+                        due,
+                        no_due,
+                        target,
+                        partial,
+                        items,
+                        tags,
+                        penalty,
+                        priority,
+                    } => match user.record(record_type, *id)? {
+                        Some(mut record) => {
+                            match &mut record {
+                                Record::Task(task) => {
+                                    task.title = name.to_string();
+                                    task.points = *points;
+                                    if let Some(timestamp) = timestamp {
+                                        task.completed_at = Some(*timestamp);
+                                    }
+                                    if let Some(recurrence) = recurrence {
+                                        task.recurrence = recurrence.clone();
+                                    }
+                                    if due.is_some() || *no_due {
+                                        task.due_at = *due;
+                                    }
+                                    if let Some(target) = target {
+                                        task.target = Some(*target).filter(|target| *target > 0);
+                                    }
+                                    if let Some(partial) = partial {
+                                        task.partial = *partial;
+                                    }
+                                    if !items.is_empty() {
+                                        task.set_checklist(items.clone());
+                                    }
+                                    if let Some(penalty) = penalty {
+                                        task.penalty = (*penalty).max(0);
+                                    }
+                                    if let Some(priority) = priority {
+                                        task.priority = *priority;
+                                    }
+                                }
+                                Record::Transaction(transaction) => {
+                                    transaction.reason = name.to_string();
+                                    transaction.amount = *points;
+                                    if let Some(timestamp) = timestamp {
+                                        transaction.timestamp = *timestamp;
+                                    }
+                                }
+                            }
+                            if !tags.is_empty() {
+                                record.set_tags(parse_tags(&tags.join(",")));
+                            }
+                            user.update_record(record)?;
+                        }
+                        None => {
+                            let options = RecordOptions {
+                                recurrence: recurrence.clone(),
+                                due: *due,
+                                target: *target,
+                                partial: partial.unwrap_or_default(),
+                                items: items.clone(),
+                                tags: tags.clone(),
+                                penalty: penalty.unwrap_or_default(),
+                                priority: priority.unwrap_or_default(),
+                            };
+                            let new_id = user.insert_record(new_record(
+                                record_type,
+                                name,
+                                *points,
+                                *timestamp,
+                                &options,
+                            ))?;
+                            println!(
+                                "Cannot find a record with id {}, inserted it as {}",
+                                id, new_id
+                            );
+                        }
+                    },
+                    ModifySub::Delete { id } => {
+                        if !user.delete_record(record_type, *id)? {
+                            println!("Cannot find a record with id {}", id);
+                        }
                     }
-                    let index = *index;
-                    if index >= record.0.len() {
-                        record.0.push((name.to_string(), *points, timestamp));
-                    } else {
-                        record.0[index] = (name.to_string(), *points, timestamp);
+                    ModifySub::Insert {
+                        name,
+                        points,
+                        timestamp,
+                        options,
+                    } => {
+                        let id = user.insert_record(new_record(
+                            record_type,
+                            name,
+                            *points,
+                            *timestamp,
+                            options,
+                        ))?;
+                        println!("Inserted the record with id {}", id);
                     }
                 }
-                ModifySub::Delete { index } => {
-                    record.0.remove(*index);
-                }
-                ModifySub::Insert {
-                    name,
-                    points,
-                    timestamp,
-                } => {
-                    let mut timestamp = *timestamp;
-                    if matches!(record_type, RecordType::Transaction) && timestamp.is_none() {
-                        timestamp = Some(Utc::now().timestamp())
-                    }
+                Ok(())
+            })
+            .expect("Cannot update for user");
+        }
 
-                    record.0.push((name.to_string(), *points, timestamp));
-                }
+        Commands::Backup { dir, keep } => {
+            let path = backup::snapshot(&db, dir, *keep).expect("Cannot back up the database");
+            println!("Backed up the database to {}", path.display());
+        }
+
+        Commands::Snapshots { dir } => {
+            for snapshot in backup::snapshots(dir).expect("Cannot list the snapshots") {
+                println!("{}", snapshot.display());
+            }
+        }
+
+        Commands::Restore { snapshot } => {
+            db.restore_from(snapshot)
+                .expect("Cannot restore the database");
+            let (from, to) = db.migrate().expect("Cannot migrate the restored database");
+            println!(
+                "Restored the database from {} at schema version {}, now at {}",
+                snapshot.display(),
+                from,
+                to
+            );
+        }
+
+        Commands::History { user_id } => {
+            for event in db.query_events(*user_id).expect("Cannot query history") {
+                let actor = match event.actor_id {
+                    Some(actor_id) => actor_id.to_string(),
+                    None => "-".to_owned(),
+                };
+                println!(
+                    "#{} {} {} via {}: {} {} #{} {:?}",
+                    event.id,
+                    NaiveDateTime::from_timestamp(event.timestamp, 0),
+                    actor,
+                    event.surface,
+                    event.action.as_str(),
+                    event.record_type.as_str(),
+                    event.record_id,
+                    event.record
+                );
             }
-            update_user(&cli.dbfile, &user).expect("Cannot update for user");
         }
+
+        Commands::Replay { user_id } => {
+            let history = db.query_events(*user_id).expect("Cannot query history");
+            let replayed = events::replay(*user_id, &history);
+            println!(
+                "Replayed {} events\nDaily Tasks: {:?}\nPeriodic Tasks: {:?}\nTransaction history: {:?}",
+                history.len(),
+                replayed.daily,
+                replayed.pending,
+                replayed.transactions
+            );
+
+            let stored = db
+                .query_user(*user_id)
+                .expect("Cannot query user")
+                .unwrap_or_else(|| db::User::new(*user_id));
+            if stored == replayed {
+                println!("The stored records match the history");
+            } else {
+                println!("The stored records differ from the history");
+            }
+        }
+
+        Commands::Catalog { path } => match Catalog::from_file(path) {
+            Ok(catalog) => {
+                for item in &catalog.items {
+                    let mut qty = item
+                        .qty
+                        .map_or_else(|| "unlimited".to_owned(), |qty| qty.to_string());
+                    if let Some(restock) = item.restock {
+                        qty += &format!(", restocks {}", restock);
+                    }
+                    if let Some(from) = item.available_from {
+                        qty += &format!(", from {}", from);
+                    }
+                    if let Some(until) = item.available_until {
+                        qty += &format!(", until {}", until);
+                    }
+                    println!("{} for {} coins ({})", item.name, item.cost, qty);
+                }
+                println!("{} has {} valid items", path.display(), catalog.items.len());
+            }
+            Err(e) => println!("{} is not a valid catalog: {}", path.display(), e),
+        },
     }
 }
@@ -1,6 +1,6 @@
 use std::{sync::Arc, time::Duration};
 
-use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use chrono::{DateTime, Utc};
 use interpolation::lerp;
 use serenity::{
     builder::CreateInteractionResponse,
@@ -13,67 +13,179 @@ use serenity::{
     utils::Color,
 };
 
-use super::util::{get_today, get_tomorrow};
-use crate::util::db::User;
+use super::util::{ask_for_proof, get_today, get_tomorrow, progress_bar, progress_steps};
+use crate::util::{
+    streaks::{self, Streak, StreakBonuses},
+    Due, Origin, RecordId, Recurrence, Settings, Source, Surface, Task, TaskKind, Transaction,
+    UserStore,
+};
+
+/// A task due today, where it stands and how long its streak is
+struct Entry {
+    task: Task,
+    due: Due,
+    streak: Streak,
+    /// Progress towards the task's target logged before this no longer counts
+    since: i64,
+}
+
+impl Entry {
+    fn progress(&self) -> u32 {
+        self.task.progress_since(self.since)
+    }
+
+    /// Whether progress is still to be logged towards the task's target today
+    fn is_counting(&self) -> bool {
+        self.task.target.is_some() && matches!(self.due, Due::Open { .. })
+    }
+}
 
 /// A struct to represent every daily tasks and corresponding files
 pub struct Daily {
-    db_file: String,
-    user: User,
+    store: Arc<dyn UserStore>,
+    user_id: u64,
+    /// Only the tasks due today
+    daily: Vec<Entry>,
+    /// Whether the user has daily tasks at all, due today or not
+    any: bool,
+    /// Only show tasks with this tag
+    tag: Option<String>,
+    /// The user's timezone and day start
+    settings: Settings,
+    bonuses: StreakBonuses,
+    /// When the embed was opened
+    opened: i64,
+    /// Streak bonuses paid since the embed was opened
+    paid: Vec<Transaction>,
+    /// Missed-day penalties taken since the embed was opened
+    missed: Vec<Transaction>,
 }
 
 impl Daily {
-    pub fn new(db_file: &str, user_id: u64) -> Self {
-        let mut user = User::from_file(db_file, user_id).unwrap();
-        user.daily.iter_mut().for_each(|mut record| {
-            if record.2.is_some() {
-                let days = DateTime::<Utc>::from_utc(
-                    NaiveDateTime::from_timestamp(record.2.unwrap(), 0),
-                    Utc,
-                );
-                if days.num_days_from_ce() != get_today().num_days_from_ce() {
-                    record.2 = None;
+    pub fn new(
+        store: &Arc<dyn UserStore>,
+        user_id: u64,
+        bonuses: &StreakBonuses,
+        tag: Option<String>,
+    ) -> Self {
+        let mut daily = Self {
+            store: store.clone(),
+            user_id,
+            daily: Vec::new(),
+            any: false,
+            tag,
+            settings: Settings::default(),
+            bonuses: bonuses.clone(),
+            opened: Utc::now().timestamp(),
+            paid: Vec::new(),
+            missed: Vec::new(),
+        };
+        daily.refresh();
+        daily
+    }
+
+    /// Take penalties for tasks missed since the last check, then fetch the tasks again and work
+    /// out which are due today, following each task's rule
+    fn refresh(&mut self) {
+        self.settings = self
+            .store
+            .settings(self.user_id)
+            .expect("Cannot fetch settings for user");
+        let missed = self
+            .store
+            .penalize_missed(Origin::system(Surface::Penalty), self.user_id)
+            .expect("Cannot penalize missed tasks for user");
+        self.missed.extend(missed);
+        self.paid = self
+            .store
+            .transactions(self.user_id)
+            .expect("Cannot fetch transactions for user")
+            .into_iter()
+            .filter(|transaction| {
+                matches!(transaction.source, Source::Streak(_))
+                    && transaction.timestamp >= self.opened
+            })
+            .collect();
+
+        let tasks = self
+            .store
+            .tasks(self.user_id, TaskKind::Daily)
+            .expect("Cannot fetch daily tasks for user")
+            .into_iter()
+            .filter(|task| self.tag.as_ref().is_none_or(|tag| task.tags.contains(tag)))
+            .collect::<Vec<_>>();
+        let today = get_today(&self.settings);
+
+        self.any = !tasks.is_empty();
+        self.daily = tasks
+            .into_iter()
+            .map(|task| {
+                let completions = self
+                    .store
+                    .completions(self.user_id, task.id)
+                    .expect("Cannot fetch completions for user")
+                    .into_iter()
+                    .map(|timestamp| self.settings.day_of(timestamp))
+                    .collect::<Vec<_>>();
+                let created = self.settings.day_of(task.created_at);
+                Entry {
+                    due: task.recurrence.due(created, &completions, today),
+                    streak: streaks::streak(&task.recurrence, created, &completions, today),
+                    since: task.counting_since(&self.settings),
+                    task,
                 }
-            }
-        });
+            })
+            .filter(|entry| entry.due != Due::Off)
+            .collect();
+    }
 
-        Self {
-            db_file: db_file.to_owned(),
-            user,
-        }
+    fn complete_task(&mut self, id: RecordId) -> Option<()> {
+        let completed = self
+            .store
+            .complete_task(
+                Origin::user(self.user_id, Surface::Daily),
+                self.user_id,
+                TaskKind::Daily,
+                id,
+                &self.bonuses,
+            )
+            .expect("Cannot update user to database");
+        self.refresh();
+        completed.map(|_| ())
     }
 
-    fn complete_task(&mut self, task_name: &str) -> Option<()> {
-        let record = self
-            .user
-            .daily
-            .iter_mut()
-            .filter(|record| record.0 == task_name)
-            .next();
-        if let Some(record) = record {
-            record.2 = Some(DateTime::timestamp(&Utc::now()));
-            self.user
-                .transactions
-                .push(record.0.to_string(), record.1, record.2);
-            self.user
-                .update(&self.db_file)
-                .expect("Cannot update user to database");
-            Some(())
-        } else {
-            None
-        }
+    /// Log progress towards a task's target
+    fn add_progress(&mut self, id: RecordId, amount: u32) -> Option<()> {
+        let progressed = self
+            .store
+            .add_progress(
+                Origin::user(self.user_id, Surface::Daily),
+                self.user_id,
+                TaskKind::Daily,
+                id,
+                amount,
+                &self.bonuses,
+            )
+            .expect("Cannot update user to database");
+        self.refresh();
+        progressed.map(|_| ())
     }
 
     fn delegate_interaction_response<'a>(
         &self,
         interaction: &'a mut CreateInteractionResponse,
     ) -> &'a mut CreateInteractionResponse {
-        if self.user.daily.len() == 0 {
+        if self.daily.is_empty() {
+            let description = match (self.any, &self.tag) {
+                (true, _) => "Nothing is due today, enjoy the break :>".to_owned(),
+                (false, Some(tag)) => format!("You have no daily tasks tagged `{}`?", tag),
+                (false, None) => "You have no daily tasks?".to_owned(),
+            };
             return interaction.interaction_response_data(|data| {
                 data.create_embed(|embed| {
                     embed
                         .title("This is a little awkward")
-                        .description("You have no daily tasks?")
+                        .description(description)
                         .footer(|footer| footer.text("Add something :< Self improvement happens when you have a routine!"))
                 })
             });
@@ -81,38 +193,70 @@ impl Daily {
 
         let mut completed = 0;
 
-        let (tasks, rewards, when) = self.user.daily.iter().fold(
-            (String::new(), String::new(), String::new()),
-            |e, record| {
-                (
-                    e.0 + &format!("{}\n", record.0),
-                    e.1 + &format!(":coin:x{}\n", record.1),
-                    e.2 + &match record.2 {
-                        Some(timestamp) => {
-                            completed += 1;
-                            let timestamp = DateTime::timestamp(&Utc::now()) - timestamp as i64;
-                            format!(
-                                "✅ Completed *{}h {}m {}s ago*\n",
-                                timestamp / 3600,
-                                timestamp % 3600 / 60,
-                                timestamp % 3600 % 60
-                            )
-                        }
-                        None => "⌛ Not Completed\n".to_owned(),
-                    },
-                )
-            },
-        );
+        let (tasks, rewards, when) =
+            self.daily
+                .iter()
+                .fold((String::new(), String::new(), String::new()), |e, entry| {
+                    let Entry {
+                        task, due, streak, ..
+                    } = entry;
+                    let rule = match &task.recurrence {
+                        Recurrence::Daily => String::new(),
+                        recurrence => format!(" *({})*", recurrence),
+                    };
+                    let streak = match streak.current {
+                        0 => String::new(),
+                        current => format!(" 🔥{} (best {})", current, streak.best),
+                    };
+                    (
+                        e.0 + &format!("`#{}` {}{}{}\n", task.id, task.title, rule, streak),
+                        e.1 + &format!(":coin:x{}\n", task.points),
+                        e.2 + &match (due, task.completed_at) {
+                            (Due::Done { .. }, Some(timestamp)) => {
+                                completed += 1;
+                                let timestamp = DateTime::timestamp(&Utc::now()) - timestamp as i64;
+                                format!(
+                                    "✅ Completed *{}h {}m {}s ago*\n",
+                                    timestamp / 3600,
+                                    timestamp % 3600 / 60,
+                                    timestamp % 3600 % 60
+                                )
+                            }
+                            (Due::Open { .. }, _) if task.target.is_some() => format!(
+                                "{}\n",
+                                progress_bar(entry.progress(), task.target.unwrap_or(1))
+                            ),
+                            (Due::Open { done, required }, _) if *done > 0 => {
+                                format!("⌛ {}/{} this week\n", done, required)
+                            }
+                            _ => "⌛ Not Completed\n".to_owned(),
+                        },
+                    )
+                });
 
-        let completed: f32 = completed as f32 / self.user.daily.len() as f32;
+        let completed: f32 = completed as f32 / self.daily.len() as f32;
+        let paid = self
+            .paid
+            .iter()
+            .map(|bonus| format!("🎉 {}! +:coin:x{}\n", bonus.reason, bonus.amount))
+            .chain(
+                self.missed
+                    .iter()
+                    .map(|penalty| format!("💀 {} :coin:x{}\n", penalty.reason, penalty.amount)),
+            )
+            .collect::<String>();
 
         interaction.interaction_response_data(|data| {
             data.create_embed(|embed| {
                 embed
                     .title(format!(
-                        "Daily tasks! :D ({}% completed)",
+                        "Daily tasks{}! :D ({}% completed)",
+                        self.tag
+                            .as_ref()
+                            .map_or_else(String::new, |tag| format!(" tagged `{}`", tag)),
                         (completed * 100_f32) as u64
                     ))
+                    .description(paid)
                     .field("Task", tasks, true)
                     .field("Rewards", rewards, true)
                     .field("Progress", when, true)
@@ -122,7 +266,7 @@ impl Daily {
                         lerp(&43, &89, &completed),
                     ))
                     .footer(|footer| {
-                        let elapsed = (get_tomorrow() - Utc::now()).num_seconds();
+                        let elapsed = (get_tomorrow(&self.settings) - Utc::now()).num_seconds();
                         footer.text(format!(
                             "{}h {}m {}s until refresh",
                             elapsed / 3600,
@@ -132,29 +276,69 @@ impl Daily {
                     })
             })
             .components(|components| {
-                if self.user.daily.iter().any(|record| record.2.is_none()) {
+                let open = self
+                    .daily
+                    .iter()
+                    .filter(|entry| matches!(entry.due, Due::Open { .. }))
+                    .filter(|entry| entry.task.target.is_none())
+                    .take(25)
+                    .collect::<Vec<_>>();
+                if !open.is_empty() {
                     components.create_action_row(|row| {
                         row.create_select_menu(|menu| {
                             menu.options(|options| {
-                                for record in &self.user.daily {
-                                    if record.2.is_none() {
-                                        options.create_option(|option| {
-                                            option
-                                                .label(&record.0)
-                                                .description(&format!("{}x coins", record.1))
-                                                .value(&record.0)
-                                        });
-                                    }
+                                for Entry { task, .. } in &open {
+                                    options.create_option(|option| {
+                                        option
+                                            .label(&task.title)
+                                            .description(&format!("{}x coins", task.points))
+                                            .value(task.id)
+                                    });
                                 }
                                 options
                             })
                             .placeholder("Pick your poison :>")
                             .custom_id("complete_daily_menu")
                         })
+                    });
+                }
+
+                let steps = self
+                    .daily
+                    .iter()
+                    .filter(|entry| entry.is_counting())
+                    .flat_map(|entry| {
+                        let (progress, target) = (entry.progress(), entry.task.target.unwrap_or(1));
+                        progress_steps(progress, target)
+                            .into_iter()
+                            .map(move |step| (&entry.task, progress, target, step))
                     })
-                } else {
-                    components
+                    .take(25)
+                    .collect::<Vec<_>>();
+                if !steps.is_empty() {
+                    components.create_action_row(|row| {
+                        row.create_select_menu(|menu| {
+                            menu.options(|options| {
+                                for (task, progress, target, step) in &steps {
+                                    options.create_option(|option| {
+                                        option
+                                            .label(format!("{} +{}", task.title, step))
+                                            .description(format!(
+                                                "{}/{} after this",
+                                                progress + step,
+                                                target
+                                            ))
+                                            .value(format!("{}:{}", task.id, step))
+                                    });
+                                }
+                                options
+                            })
+                            .placeholder("Log some progress :>")
+                            .custom_id("log_progress_menu")
+                        })
+                    });
                 }
+                components
             });
             data
         })
@@ -178,14 +362,25 @@ impl Daily {
             .unwrap()
             .await_component_interactions(shard_messenger)
             .timeout(Duration::from_secs(15))
-            .author_id(self.user.id)
+            .author_id(self.user_id)
             .await;
 
         let daily = &Arc::new(Mutex::new(self));
         collector
             .for_each(|interaction| async move {
                 let mut daily = daily.lock().await;
-                daily.complete_task(&interaction.data.values[0]);
+                let value = &interaction.data.values[0];
+                let mut completed = None;
+                if interaction.data.custom_id == "log_progress_menu" {
+                    let step = value
+                        .split_once(':')
+                        .and_then(|(id, amount)| Some((id.parse().ok()?, amount.parse().ok()?)));
+                    if let Some((id, amount)) = step {
+                        daily.add_progress(id, amount);
+                    }
+                } else if let Ok(id) = value.parse() {
+                    completed = daily.complete_task(id).map(|_| id);
+                }
                 interaction
                     .create_interaction_response(http, |interaction| {
                         daily
@@ -194,9 +389,85 @@ impl Daily {
                     })
                     .await
                     .expect("Unable to update interaction");
+
+                if let Some(id) = completed {
+                    let proof = ask_for_proof(
+                        http.clone(),
+                        shard_messenger.clone(),
+                        interaction.clone(),
+                        daily.store.clone(),
+                        Surface::Daily,
+                        TaskKind::Daily,
+                        id,
+                    );
+                    tokio::spawn(async move {
+                        if let Err(e) = proof.await {
+                            println!("Cannot collect proof: {}", e);
+                        }
+                    });
+                }
             })
             .await;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{store::tests::stores, Record};
+
+    #[test]
+    fn complete_task_pays_and_marks_it_done() {
+        for store in stores() {
+            let origin = Origin::user(1, Surface::Modify);
+            let id = store
+                .insert_record(
+                    origin,
+                    1,
+                    Record::Task(Task::new(TaskKind::Daily, "Gym", 10)),
+                )
+                .unwrap();
+            let mut daily = Daily::new(&store, 1, &StreakBonuses::default(), None);
+            assert!(matches!(daily.daily[0].due, Due::Open { .. }));
+
+            assert_eq!(daily.complete_task(id), Some(()));
+            assert!(matches!(daily.daily[0].due, Due::Done { .. }));
+            assert_eq!(daily.daily[0].streak.current, 1);
+            let transactions = store.transactions(1).unwrap();
+            assert_eq!(transactions.len(), 1);
+            assert_eq!(transactions[0].amount, 10);
+            assert_eq!(transactions[0].source, Source::Task(id));
+
+            assert_eq!(daily.complete_task(id + 100), None);
+            assert_eq!(store.transactions(1).unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn tag_filter_only_shows_tagged_tasks() {
+        for store in stores() {
+            let origin = Origin::user(1, Surface::Modify);
+            let mut gym = Task::new(TaskKind::Daily, "Gym", 10);
+            gym.tags = vec!["health".to_owned()];
+            store.insert_record(origin, 1, Record::Task(gym)).unwrap();
+            store
+                .insert_record(
+                    origin,
+                    1,
+                    Record::Task(Task::new(TaskKind::Daily, "Email", 5)),
+                )
+                .unwrap();
+
+            let daily = Daily::new(
+                &store,
+                1,
+                &StreakBonuses::default(),
+                Some("health".to_owned()),
+            );
+            assert_eq!(daily.daily.len(), 1);
+            assert_eq!(daily.daily[0].task.title, "Gym");
+        }
+    }
+}
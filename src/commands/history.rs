@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use serenity::{
+    builder::CreateApplicationCommand,
+    http::Http,
+    model::interactions::application_command::{
+        ApplicationCommandInteraction,
+        ApplicationCommandInteractionDataOptionValue as ApplicationValue,
+        ApplicationCommandOptionType,
+    },
+};
+
+use crate::util::{Completion, Settings, TaskKind, UserStore};
+
+/// How many completions are listed at most, newest first
+const SHOWN_COMPLETIONS: usize = 15;
+
+/// How long the listed completions may get, leaving room for the rest of the message
+const SHOWN_LENGTH: usize = 1800;
+
+/// List every time a task was completed, with the proof kept for each
+pub struct History<'a> {
+    store: &'a dyn UserStore,
+    user_id: u64,
+}
+
+impl<'a> History<'a> {
+    pub fn new(store: &'a dyn UserStore, user_id: u64) -> Self {
+        Self { store, user_id }
+    }
+
+    pub fn create_application_command() -> CreateApplicationCommand {
+        let mut command = CreateApplicationCommand::default();
+        command
+            .name("history")
+            .description("See every time you completed a task, and the proof you kept!")
+            .create_option(|option| {
+                option
+                    .name("id")
+                    .description("The task's id, shown next to its name")
+                    .required(true)
+                    .kind(ApplicationCommandOptionType::Integer)
+            });
+        command
+    }
+
+    /// One line per completion, e.g. "`01/31/2022 (06:00 PM)` ran 5k ([attachment](...))"
+    fn describe(completion: &Completion, settings: &Settings) -> String {
+        let when = settings.format(completion.completed, "%m/%d/%Y (%I:%M %p)");
+        match &completion.proof {
+            Some(proof) => format!("`{}` {}", when, proof),
+            None => format!("`{}`", when),
+        }
+    }
+
+    /// The history of the task with the given id, or why there is none
+    fn history(&self, id: i64) -> String {
+        let task = [TaskKind::Daily, TaskKind::Pending]
+            .into_iter()
+            .flat_map(|kind| {
+                self.store
+                    .tasks(self.user_id, kind)
+                    .expect("Cannot fetch tasks for user")
+            })
+            .find(|task| task.id == id);
+        let task = match task {
+            Some(task) => task,
+            None => return "There is no task with that id :<".to_owned(),
+        };
+
+        let completions = self
+            .store
+            .completion_history(self.user_id, task.id)
+            .expect("Cannot fetch completions for user");
+        if completions.is_empty() {
+            return format!("You haven't completed **{}** yet :>", task.title);
+        }
+
+        let settings = self
+            .store
+            .settings(self.user_id)
+            .expect("Cannot fetch settings for user");
+        let mut lines = Vec::new();
+        let mut length = 0;
+        for line in completions
+            .iter()
+            .rev()
+            .take(SHOWN_COMPLETIONS)
+            .map(|completion| Self::describe(completion, &settings))
+        {
+            length += line.chars().count() + 1;
+            if length > SHOWN_LENGTH {
+                break;
+            }
+            lines.push(line);
+        }
+        if completions.len() > lines.len() {
+            lines.push(format!(
+                "...and {} earlier",
+                completions.len() - lines.len()
+            ));
+        }
+        format!(
+            "**{}** was completed {} times:\n{}",
+            task.title,
+            completions.len(),
+            lines.join("\n")
+        )
+    }
+
+    pub async fn handle_interaction(
+        &mut self,
+        http: &Arc<Http>,
+        interaction: ApplicationCommandInteraction,
+    ) -> Result<(), serenity::Error> {
+        let id = interaction.data.options.iter().find_map(|option| {
+            match (option.name.as_str(), &option.resolved) {
+                ("id", Some(ApplicationValue::Integer(id))) => Some(*id),
+                _ => None,
+            }
+        });
+        let response = match id {
+            Some(id) => self.history(id),
+            None => "Which task? Give me its id :<".to_owned(),
+        };
+
+        interaction
+            .create_interaction_response(http, |reply| {
+                reply.interaction_response_data(|data| data.content(response))
+            })
+            .await?;
+
+        Ok(())
+    }
+}
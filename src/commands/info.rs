@@ -7,17 +7,32 @@ use std::{
     sync::Arc,
 };
 
-use crate::util::db::{self, query_user};
+use super::util::breakdown;
+use crate::util::{Source, Transaction, UserStore};
 
-pub struct Info(db::User);
+pub struct Info {
+    transactions: Vec<Transaction>,
+}
 
 impl Info {
-    pub fn new(db_path: &str, user_id: u64) -> Self {
-        Self(
-            query_user(db_path, user_id)
-                .expect("Cannot cannot to the database")
-                .expect("Get user under the provided id"),
-        )
+    pub fn new(store: &Arc<dyn UserStore>, user_id: u64) -> Self {
+        Self {
+            transactions: store
+                .transactions(user_id)
+                .expect("Cannot cannot to the database"),
+        }
+    }
+
+    /// How many daily tasks were missed, and the coins lost to them
+    fn missed(&self) -> String {
+        let (count, lost) = self
+            .transactions
+            .iter()
+            .filter(|transaction| matches!(transaction.source, Source::Missed(_)))
+            .fold((0, 0), |(count, lost), transaction| {
+                (count + 1, lost - transaction.amount)
+            });
+        format!("{} missed, :coin:x{} lost", count, lost)
     }
 
     fn delegate_interaction_response<'a>(
@@ -31,28 +46,31 @@ impl Info {
                     .title("About you!")
                     .field(
                         "__**Points Gathered**__",
-                        self.0
-                            .transactions
+                        self.transactions
                             .iter()
-                            .fold(0, |acc, record| acc + max(0, record.1)),
+                            .fold(0, |acc, transaction| acc + max(0, transaction.amount)),
                         true,
                     )
                     .field(
                         "__**Points Spent**__",
-                        self.0
-                            .transactions
+                        self.transactions
                             .iter()
-                            .fold(0, |acc, record| acc + -min(0, record.1)),
+                            .fold(0, |acc, transaction| acc + -min(0, transaction.amount)),
                         true,
                     )
                     .field(
                         "__**Points Balance**__",
-                        self.0
-                            .transactions
+                        self.transactions
                             .iter()
-                            .fold(0, |acc, record| acc + record.1),
+                            .fold(0, |acc, transaction| acc + transaction.amount),
                         true,
                     )
+                    .field("__**Missed Tasks**__", self.missed(), true)
+                    .field(
+                        "__**Points by Category**__",
+                        breakdown(&self.transactions),
+                        false,
+                    )
                     .thumbnail(user.avatar_url().unwrap())
             })
         })
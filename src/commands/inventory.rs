@@ -0,0 +1,245 @@
+use std::{sync::Arc, time::Duration};
+
+use serenity::{
+    builder::{CreateApplicationCommand, CreateInteractionResponse},
+    client::bridge::gateway::ShardMessenger,
+    futures::{lock::Mutex, StreamExt},
+    http::Http,
+    model::interactions::{
+        application_command::ApplicationCommandInteraction, InteractionResponseType,
+    },
+};
+
+use crate::util::{Origin, RecordId, Settings, Surface, Transaction, UserStore};
+
+/// How many rewards of each kind to list, so the embed fields stay short
+const SHOWN_REWARDS: usize = 10;
+
+/// The rewards a user bought in the shop, redeemed when they actually use them
+pub struct Inventory {
+    store: Arc<dyn UserStore>,
+    user_id: u64,
+    /// Dates are shown in the user's timezone
+    settings: Settings,
+    /// Every purchase that bought a reward, oldest first
+    rewards: Vec<Transaction>,
+    /// What came of the last redemption
+    notice: Option<String>,
+}
+
+impl Inventory {
+    pub fn new(store: &Arc<dyn UserStore>, user_id: u64) -> Self {
+        let mut inventory = Self {
+            store: store.clone(),
+            user_id,
+            settings: store
+                .settings(user_id)
+                .expect("Cannot fetch settings for user"),
+            rewards: Vec::new(),
+            notice: None,
+        };
+        inventory.refresh();
+        inventory
+    }
+
+    pub fn create_application_command() -> CreateApplicationCommand {
+        let mut command = CreateApplicationCommand::default();
+        command
+            .name("inventory")
+            .description("See the rewards you bought and use them :D");
+        command
+    }
+
+    fn refresh(&mut self) {
+        self.rewards = self
+            .store
+            .transactions(self.user_id)
+            .expect("Cannot fetch transactions for user")
+            .into_iter()
+            .filter(|transaction| transaction.reward.is_some())
+            .collect();
+    }
+
+    fn redeem(&mut self, id: RecordId) {
+        let redeemed = self
+            .store
+            .redeem(
+                Origin::user(self.user_id, Surface::Inventory),
+                self.user_id,
+                id,
+            )
+            .expect("Cannot redeem reward for user");
+        self.refresh();
+
+        let item = self
+            .rewards
+            .iter()
+            .find(|transaction| transaction.id == id)
+            .and_then(|transaction| transaction.reward.as_ref())
+            .map(|reward| reward.item.as_str());
+        self.notice = Some(match (redeemed, item) {
+            (true, Some(item)) => format!("Enjoy your **{}**! :D", item),
+            _ => "That reward has already been used :<".to_owned(),
+        });
+    }
+
+    fn date(&self, timestamp: i64) -> String {
+        self.settings.format(timestamp, "%m/%d/%Y")
+    }
+
+    fn delegate_interaction_response<'a>(
+        &self,
+        interaction: &'a mut CreateInteractionResponse,
+    ) -> &'a mut CreateInteractionResponse {
+        if self.rewards.is_empty() {
+            return interaction.interaction_response_data(|data| {
+                data.create_embed(|embed| {
+                    embed
+                        .title("Your inventory is empty!")
+                        .description("Treat yourself to something from `/shop browse` :D")
+                })
+                .components(|components| components)
+            });
+        }
+
+        let owned = self
+            .rewards
+            .iter()
+            .filter(|transaction| transaction.is_owned())
+            .collect::<Vec<_>>();
+        let mut redeemed = self
+            .rewards
+            .iter()
+            .filter_map(|transaction| {
+                let reward = transaction.reward.as_ref()?;
+                Some((transaction, &reward.item, reward.redeemed_at?))
+            })
+            .collect::<Vec<_>>();
+        redeemed.sort_by_key(|(transaction, _, redeemed_at)| {
+            std::cmp::Reverse((*redeemed_at, transaction.id))
+        });
+
+        let mut owned_list = owned
+            .iter()
+            .take(SHOWN_REWARDS)
+            .filter_map(|transaction| {
+                let reward = transaction.reward.as_ref()?;
+                Some(format!(
+                    "`#{}` {} (bought {})\n",
+                    transaction.id,
+                    reward.item,
+                    self.date(transaction.timestamp)
+                ))
+            })
+            .collect::<String>();
+        if owned.len() > SHOWN_REWARDS {
+            owned_list += &format!("…and {} more\n", owned.len() - SHOWN_REWARDS);
+        }
+        let redeemed_list = redeemed
+            .iter()
+            .take(SHOWN_REWARDS)
+            .map(|(transaction, item, redeemed_at)| {
+                format!(
+                    "`#{}` {} on {} (bought {})\n",
+                    transaction.id,
+                    item,
+                    self.date(*redeemed_at),
+                    self.date(transaction.timestamp)
+                )
+            })
+            .collect::<String>();
+
+        interaction.interaction_response_data(|data| {
+            data.create_embed(|embed| {
+                embed.title("Your inventory :>").field(
+                    format!("Waiting to be used ({})", owned.len()),
+                    if owned_list.is_empty() {
+                        "Nothing, time to go shopping? :>".to_owned()
+                    } else {
+                        owned_list
+                    },
+                    false,
+                );
+                if !redeemed_list.is_empty() {
+                    embed.field(format!("Used ({})", redeemed.len()), redeemed_list, false);
+                }
+                if let Some(notice) = &self.notice {
+                    embed.description(notice);
+                }
+                embed
+            })
+            .components(|components| {
+                if !owned.is_empty() {
+                    components.create_action_row(|row| {
+                        row.create_select_menu(|menu| {
+                            menu.options(|options| {
+                                for transaction in owned.iter().take(25) {
+                                    options.create_option(|option| {
+                                        option
+                                            .label(
+                                                transaction
+                                                    .reward
+                                                    .as_ref()
+                                                    .map_or("", |reward| &reward.item),
+                                            )
+                                            .description(format!(
+                                                "Bought {}",
+                                                self.date(transaction.timestamp)
+                                            ))
+                                            .value(transaction.id)
+                                    });
+                                }
+                                options
+                            })
+                            .placeholder("Use a reward :D")
+                            .custom_id("redeem_item_menu")
+                        })
+                    });
+                }
+                components
+            });
+            data
+        })
+    }
+
+    pub async fn handle_interaction(
+        &mut self,
+        http: &Arc<Http>,
+        interaction: ApplicationCommandInteraction,
+        shard_messenger: &ShardMessenger,
+    ) -> Result<(), serenity::Error> {
+        interaction
+            .create_interaction_response(http, |interaction| {
+                self.delegate_interaction_response(interaction)
+            })
+            .await?;
+
+        let collector = interaction
+            .get_interaction_response(http)
+            .await?
+            .await_component_interactions(shard_messenger)
+            .timeout(Duration::from_secs(30))
+            .author_id(self.user_id)
+            .await;
+
+        let inventory = &Arc::new(Mutex::new(self));
+        collector
+            .for_each(|interaction| async move {
+                let mut inventory = inventory.lock().await;
+                if let Ok(id) = interaction.data.values[0].parse() {
+                    inventory.redeem(id);
+                }
+                interaction
+                    .create_interaction_response(http, |interaction| {
+                        inventory
+                            .delegate_interaction_response(interaction)
+                            .kind(InteractionResponseType::UpdateMessage)
+                    })
+                    .await
+                    .expect("Unable to update interaction");
+            })
+            .await;
+
+        Ok(())
+    }
+}
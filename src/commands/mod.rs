@@ -1,11 +1,22 @@
 pub mod daily;
+pub mod history;
 pub mod info;
+pub mod inventory;
 pub mod modify;
 pub mod pending;
+pub mod settings;
 pub mod shop;
 pub mod transactions;
+pub mod trash;
+pub mod undo;
 pub mod util;
 
 pub use daily::Daily;
+pub use history::History;
+pub use inventory::Inventory;
 pub use pending::Pending;
+pub use settings::Preferences;
+pub use shop::Shop;
 pub use transactions::Transactions;
+pub use trash::Trash;
+pub use undo::Undo;
@@ -1,7 +1,10 @@
 use std::{collections::HashMap, sync::Arc};
 
-use crate::util::db::{update_user, User};
-use chrono::Utc;
+use super::util::{parse_checklist, parse_datetime};
+use crate::util::{
+    db::RecordType, records::parse_tags, Origin, Priority, Record, RecordId, Recurrence, Source,
+    Surface, Task, Transaction, UserStore,
+};
 use serenity::{
     builder::CreateApplicationCommand,
     http::Http,
@@ -29,13 +32,13 @@ macro_rules! cast_opt {
 }
 
 pub struct Modify<'a> {
-    db_path: &'a str,
+    store: &'a dyn UserStore,
     user_id: u64,
 }
 
 impl<'a> Modify<'a> {
-    pub fn new(db_path: &'a str, user_id: u64) -> Self {
-        Self { db_path, user_id }
+    pub fn new(store: &'a dyn UserStore, user_id: u64) -> Self {
+        Self { store, user_id }
     }
 
     pub fn create_application_command() -> CreateApplicationCommand {
@@ -76,6 +79,58 @@ impl<'a> Modify<'a> {
                             .description("The timestamp for when the task was completed")
                             .kind(ApplicationCommandOptionType::Integer)
                     })
+                    .create_sub_option(|option| {
+                        option
+                            .name("recurrence")
+                            .description("How often a daily task is due: daily, mon,wed,fri, every 3 days, 3 per week or monthly 15")
+                            .kind(ApplicationCommandOptionType::String)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("due")
+                            .description("When a pending task is due, e.g. 2022-01-31 or 2022-01-31 18:00 in your timezone")
+                            .kind(ApplicationCommandOptionType::String)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("target")
+                            .description("How much there is to do, e.g. 8 for 8 glasses of water")
+                            .kind(ApplicationCommandOptionType::Integer)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("partial")
+                            .description("Pay points as progress is made instead of on reaching the target")
+                            .kind(ApplicationCommandOptionType::Boolean)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("checklist")
+                            .description("Steps of a pending task separated by ;, e.g. vacuum; dishes; laundry")
+                            .kind(ApplicationCommandOptionType::String)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("tags")
+                            .description("Tags such as health, work or chores. Transactions a task pays get its tags")
+                            .kind(ApplicationCommandOptionType::String)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("penalty")
+                            .description("Points taken for every day a daily task is missed")
+                            .kind(ApplicationCommandOptionType::Integer)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("priority")
+                            .description("How urgent the task is, pending tasks are sorted by it. Normal by default")
+                            .add_string_choice("Low", "low")
+                            .add_string_choice("Normal", "normal")
+                            .add_string_choice("High", "high")
+                            .add_string_choice("Urgent", "urgent")
+                            .kind(ApplicationCommandOptionType::String)
+                    })
                     .description("insert into a task into a record type!")
                     .kind(ApplicationCommandOptionType::SubCommand)
             })
@@ -84,8 +139,8 @@ impl<'a> Modify<'a> {
                     .name("update")
                     .create_sub_option(|option| {
                         option
-                            .name("index")
-                            .description("The record's index")
+                            .name("id")
+                            .description("The record's id, shown next to its name")
                             .required(true)
                             .kind(ApplicationCommandOptionType::Integer)
                     })
@@ -116,9 +171,61 @@ impl<'a> Modify<'a> {
                     .create_sub_option(|option| {
                         option
                             .name("timestamp")
-                            .description("The timestamp for when the task was completed")
+                            .description("The timestamp for when the task was completed, kept if left out")
+                            .kind(ApplicationCommandOptionType::Integer)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("recurrence")
+                            .description("How often a daily task is due: daily, mon,wed,fri, every 3 days, 3 per week or monthly 15")
+                            .kind(ApplicationCommandOptionType::String)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("due")
+                            .description("When a pending task is due, e.g. 2022-01-31 or 2022-01-31 18:00 in your timezone, or none")
+                            .kind(ApplicationCommandOptionType::String)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("target")
+                            .description("How much there is to do, e.g. 8 for 8 glasses of water, or 0 for none")
+                            .kind(ApplicationCommandOptionType::Integer)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("partial")
+                            .description("Pay points as progress is made instead of on reaching the target")
+                            .kind(ApplicationCommandOptionType::Boolean)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("checklist")
+                            .description("Steps of a pending task separated by ;, or none. Checked steps stay checked")
+                            .kind(ApplicationCommandOptionType::String)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("tags")
+                            .description("Tags such as health, work or chores, or none. Kept if left out")
+                            .kind(ApplicationCommandOptionType::String)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("penalty")
+                            .description("Points taken for every day a daily task is missed, 0 for none")
                             .kind(ApplicationCommandOptionType::Integer)
                     })
+                    .create_sub_option(|option| {
+                        option
+                            .name("priority")
+                            .description("How urgent the task is, pending tasks are sorted by it. Kept if left out")
+                            .add_string_choice("Low", "low")
+                            .add_string_choice("Normal", "normal")
+                            .add_string_choice("High", "high")
+                            .add_string_choice("Urgent", "urgent")
+                            .kind(ApplicationCommandOptionType::String)
+                    })
                     .description("update an existing task of a record type!")
                     .kind(ApplicationCommandOptionType::SubCommand)
             })
@@ -138,8 +245,8 @@ impl<'a> Modify<'a> {
                     })
                     .create_sub_option(|option| {
                         option
-                            .name("index")
-                            .description("The record's index")
+                            .name("id")
+                            .description("The record's id, shown next to its name")
                             .required(true)
                             .kind(ApplicationCommandOptionType::Integer)
                     })
@@ -149,71 +256,309 @@ impl<'a> Modify<'a> {
         command
     }
 
+    /// The record of a type with the given id, if this user has it
+    fn existing(&self, record_type: RecordType, id: RecordId) -> Option<Record> {
+        match record_type.task_kind() {
+            Some(kind) => self
+                .store
+                .tasks(self.user_id, kind)
+                .expect("Cannot fetch tasks for user")
+                .into_iter()
+                .find(|task| task.id == id)
+                .map(Record::Task),
+            None => self
+                .store
+                .transactions(self.user_id)
+                .expect("Cannot fetch transactions for user")
+                .into_iter()
+                .find(|transaction| transaction.id == id)
+                .map(Record::Transaction),
+        }
+    }
+
+    fn altered(found: bool) -> String {
+        if found {
+            "Your record has been altered!".to_owned()
+        } else {
+            "There is no record with that id :<".to_owned()
+        }
+    }
+
     pub async fn handle_interaction(
         &mut self,
         http: &Arc<Http>,
         interaction: ApplicationCommandInteraction,
     ) -> Result<(), serenity::Error> {
-        let mut user = User::from_file(self.db_path, self.user_id).unwrap();
-
         let option = interaction.data.options.first().unwrap();
         let options: HashMap<&str, _> = option
             .options
             .iter()
             .map(|option| (option.name.as_str(), option.resolved.as_ref().unwrap()))
             .collect();
+        let response = self.respond(&option.name, &options);
+
+        interaction
+            .create_interaction_response(http, |reply| {
+                reply.interaction_response_data(|data| data.content(response))
+            })
+            .await?;
+
+        Ok(())
+    }
 
+    /// Carry out `subcommand` with the options it was given, returning what to tell the user
+    fn respond(&self, subcommand: &str, options: &HashMap<&str, &ApplicationValue>) -> String {
         let name = cast_opt!(options.get("name"), ApplicationValue::String);
         let points = cast_opt!(options.get("points"), ApplicationValue::Integer);
         let record_type = cast_opt!(options.get("record_type"), ApplicationValue::String);
         let timestamp = cast_opt!(options.get("timestamp"), ApplicationValue::Integer);
-        let index = cast_opt!(options.get("index"), ApplicationValue::Integer);
-
-        let record = record_type.map_or(None, |record_type| {
-            Some(match record_type.as_str() {
-                "daily" => &mut user.daily,
-                "pending" => &mut user.pending,
-                "transaction" => &mut user.transactions,
-                _ => panic!("Unknown record type!"),
+        let id = cast_opt!(options.get("id"), ApplicationValue::Integer);
+        let recurrence = cast_opt!(options.get("recurrence"), ApplicationValue::String);
+        let due = cast_opt!(options.get("due"), ApplicationValue::String);
+        let target = cast_opt!(options.get("target"), ApplicationValue::Integer);
+        let partial = cast_opt!(options.get("partial"), ApplicationValue::Boolean);
+        let checklist = cast_opt!(options.get("checklist"), ApplicationValue::String);
+        let penalty = cast_opt!(options.get("penalty"), ApplicationValue::Integer)
+            .map(|penalty| (*penalty).max(0));
+        let priority = cast_opt!(options.get("priority"), ApplicationValue::String)
+            .and_then(|priority| Priority::parse(priority));
+        let tags = cast_opt!(options.get("tags"), ApplicationValue::String).map(|tags| match tags
+            .trim()
+        {
+            "none" => Vec::new(),
+            tags => parse_tags(tags),
+        });
+
+        let record_type = match record_type.unwrap().as_str() {
+            "daily" => RecordType::Daily,
+            "pending" => RecordType::Pending,
+            "transaction" => RecordType::Transaction,
+            _ => panic!("Unknown record type!"),
+        };
+
+        let recurrence = recurrence
+            .map(|recurrence| Recurrence::parse(recurrence).ok_or_else(|| {
+                format!(
+                    "I don't understand `{}` :< Try `daily`, `mon,wed,fri`, `every 3 days`, `3 per week` or `monthly 15`",
+                    recurrence
+                )
+            }))
+            .transpose();
+        // Due dates are typed in the user's timezone
+        let settings = self
+            .store
+            .settings(self.user_id)
+            .expect("Cannot fetch settings for user");
+        // `Some(None)` clears the due date
+        let due = due
+            .map(|due| match due.trim() {
+                "none" => Ok(None),
+                due => parse_datetime(due, &settings).map(Some).ok_or_else(|| {
+                    format!(
+                        "I don't understand `{}` :< Try `2022-01-31` or `2022-01-31 18:00`",
+                        due
+                    )
+                }),
             })
+            .transpose();
+
+        // `Some(None)` clears the target
+        let target = target.map(|target| u32::try_from(*target).ok().filter(|target| *target > 0));
+
+        let checklist = checklist.map(|checklist| match checklist.trim() {
+            "none" => Vec::new(),
+            checklist => parse_checklist(checklist),
         });
+        if checklist.is_some() && record_type != RecordType::Pending {
+            return "Only pending tasks can have a checklist :<".to_owned();
+        }
 
-        match option.name.as_str() {
-            "insert" => record.unwrap().push(
-                name.unwrap().to_owned(),
-                *points.unwrap(),
-                timestamp.map_or_else(
-                    || {
-                        if record_type.unwrap() == "transaction" {
-                            Some(Utc::now().timestamp())
-                        } else {
-                            None
+        let (recurrence, due) = match (recurrence, due) {
+            (Ok(recurrence), Ok(due)) => (recurrence, due),
+            (Err(response), _) | (_, Err(response)) => return response,
+        };
+
+        let origin = Origin::user(self.user_id, Surface::Modify);
+        match subcommand {
+            "insert" => {
+                let name = name.unwrap().to_owned();
+                let mut record = match record_type.task_kind() {
+                    Some(kind) => {
+                        let mut task = Task::new(kind, name, *points.unwrap());
+                        task.completed_at = timestamp.copied();
+                        task.recurrence = recurrence.unwrap_or_default();
+                        task.due_at = due.flatten();
+                        task.target = target.flatten();
+                        task.partial = partial.copied().unwrap_or_default();
+                        task.set_checklist(checklist.unwrap_or_default());
+                        task.penalty = penalty.unwrap_or_default();
+                        task.priority = priority.unwrap_or_default();
+                        Record::Task(task)
+                    }
+                    None => {
+                        let mut transaction =
+                            Transaction::new(name, *points.unwrap(), Source::Manual);
+                        if let Some(timestamp) = timestamp {
+                            transaction.timestamp = *timestamp;
                         }
-                    },
-                    |ts| Some(*ts),
-                ),
-            ),
-            "update" => {
-                record.unwrap().0[*index.unwrap() as usize] = (
-                    name.unwrap().to_owned(),
-                    *points.unwrap(),
-                    timestamp.map(|ts| *ts),
-                );
-            }
-            "delete" => {
-                record.unwrap().0.remove(*index.unwrap() as usize);
+                        Record::Transaction(transaction)
+                    }
+                };
+                record.set_tags(tags.unwrap_or_default());
+                self.store
+                    .insert_record(origin, self.user_id, record)
+                    .map(|id| format!("Your record has been added as `#{}`!", id))
             }
+            "update" => match self.existing(record_type, *id.unwrap()) {
+                Some(mut record) => {
+                    match &mut record {
+                        Record::Task(task) => {
+                            task.title = name.unwrap().to_owned();
+                            task.points = *points.unwrap();
+                            if let Some(timestamp) = timestamp {
+                                task.completed_at = Some(*timestamp);
+                            }
+                            if let Some(recurrence) = recurrence {
+                                task.recurrence = recurrence;
+                            }
+                            if let Some(due) = due {
+                                task.due_at = due;
+                            }
+                            if let Some(target) = target {
+                                task.target = target;
+                            }
+                            if let Some(partial) = partial {
+                                task.partial = *partial;
+                            }
+                            if let Some(checklist) = checklist {
+                                task.set_checklist(checklist);
+                            }
+                            if let Some(penalty) = penalty {
+                                task.penalty = penalty;
+                            }
+                            if let Some(priority) = priority {
+                                task.priority = priority;
+                            }
+                        }
+                        Record::Transaction(transaction) => {
+                            transaction.reason = name.unwrap().to_owned();
+                            transaction.amount = *points.unwrap();
+                            if let Some(timestamp) = timestamp {
+                                transaction.timestamp = *timestamp;
+                            }
+                        }
+                    }
+                    if let Some(tags) = tags {
+                        record.set_tags(tags);
+                    }
+                    self.store
+                        .update_record(origin, self.user_id, record)
+                        .map(Self::altered)
+                }
+                None => Ok(Self::altered(false)),
+            },
+            "delete" => self
+                .store
+                .delete_record(origin, self.user_id, record_type, *id.unwrap())
+                .map(|found| {
+                    if found {
+                        "Your record has been moved to the trash! `/undo` brings it back".to_owned()
+                    } else {
+                        Self::altered(found)
+                    }
+                }),
             _ => panic!("Cannot handle modify interaction"),
         }
-        update_user(self.db_path, &user).unwrap();
+        .expect("Cannot modify records for user")
+    }
+}
 
-        interaction
-            .create_interaction_response(http, |response| {
-                response
-                    .interaction_response_data(|data| data.content("Your record has been altered!"))
-            })
-            .await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{store::tests::stores, TaskKind};
 
-        Ok(())
+    fn string(value: &str) -> ApplicationValue {
+        ApplicationValue::String(value.to_owned())
+    }
+
+    #[test]
+    fn insert_update_and_delete_tasks() {
+        for store in stores() {
+            let modify = Modify::new(store.as_ref(), 1);
+            let (daily, walk, points) = (
+                string("daily"),
+                string("Walk"),
+                ApplicationValue::Integer(10),
+            );
+            let mut options = HashMap::from([
+                ("record_type", &daily),
+                ("name", &walk),
+                ("points", &points),
+            ]);
+            assert!(modify
+                .respond("insert", &options)
+                .starts_with("Your record has been added"));
+            let task = store.tasks(1, TaskKind::Daily).unwrap().remove(0);
+            assert_eq!((task.title.as_str(), task.points), ("Walk", 10));
+
+            let (id, run, tags) = (
+                ApplicationValue::Integer(task.id),
+                string("Run"),
+                string("health"),
+            );
+            options.insert("id", &id);
+            options.insert("name", &run);
+            options.insert("tags", &tags);
+            assert_eq!(modify.respond("update", &options), Modify::altered(true));
+            let updated = store.tasks(1, TaskKind::Daily).unwrap().remove(0);
+            assert_eq!(updated.title, "Run");
+            assert_eq!(updated.tags, vec!["health".to_owned()]);
+            assert_eq!(updated.created_at, task.created_at);
+
+            let missing = ApplicationValue::Integer(task.id + 100);
+            options.insert("id", &missing);
+            assert_eq!(modify.respond("update", &options), Modify::altered(false));
+
+            let options = HashMap::from([("record_type", &daily), ("id", &id)]);
+            assert!(modify
+                .respond("delete", &options)
+                .contains("moved to the trash"));
+            assert!(store.tasks(1, TaskKind::Daily).unwrap().is_empty());
+            assert_eq!(store.trash(1).unwrap().len(), 2);
+        }
+    }
+
+    #[test]
+    fn invalid_options_change_nothing() {
+        for store in stores() {
+            let modify = Modify::new(store.as_ref(), 1);
+            let (daily, walk, points) = (
+                string("daily"),
+                string("Walk"),
+                ApplicationValue::Integer(10),
+            );
+            let (checklist, recurrence) = (string("a; b"), string("every full moon"));
+            let options = HashMap::from([
+                ("record_type", &daily),
+                ("name", &walk),
+                ("points", &points),
+                ("checklist", &checklist),
+            ]);
+            assert_eq!(
+                modify.respond("insert", &options),
+                "Only pending tasks can have a checklist :<"
+            );
+            let options = HashMap::from([
+                ("record_type", &daily),
+                ("name", &walk),
+                ("points", &points),
+                ("recurrence", &recurrence),
+            ]);
+            assert!(modify
+                .respond("insert", &options)
+                .starts_with("I don't understand"));
+            assert!(store.tasks(1, TaskKind::Daily).unwrap().is_empty());
+        }
     }
 }
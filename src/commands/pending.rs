@@ -1,5 +1,4 @@
-use crate::util::db::User;
-use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use chrono::{DateTime, Utc};
 use interpolation::lerp;
 use serenity::{
     builder::CreateInteractionResponse,
@@ -11,67 +10,162 @@ use serenity::{
     },
     utils::Color,
 };
-use std::{sync::Arc, time::Duration};
+use std::{cmp::Reverse, sync::Arc, time::Duration};
 
-use super::util::{get_today, get_tomorrow};
-use crate::util::Records;
+use super::util::{ask_for_proof, get_today, get_tomorrow, progress_bar, progress_steps};
+use crate::util::{
+    penalty::PenaltyConfig, streaks::StreakBonuses, Origin, RecordId, Settings, Surface, Task,
+    TaskKind, Transaction, UserStore,
+};
+
+/// A duration such as "2d 3h" or "4h 12m"
+fn span(seconds: i64) -> String {
+    if seconds >= 24 * 3600 {
+        format!(
+            "{}d {}h",
+            seconds / (24 * 3600),
+            seconds % (24 * 3600) / 3600
+        )
+    } else {
+        format!("{}h {}m", seconds / 3600, seconds % 3600 / 60)
+    }
+}
 
 pub struct Pending {
-    db_file: String,
-    user: User,
+    store: Arc<dyn UserStore>,
+    user_id: u64,
+    pending: Vec<Task>,
+    /// Points taken for each task that goes overdue
+    penalty: i64,
+    /// Penalties taken since the embed was opened
+    penalized: Vec<Transaction>,
+    /// Only show tasks with this tag
+    tag: Option<String>,
+    /// The user's timezone and day start
+    settings: Settings,
 }
 
 impl Pending {
-    pub fn new(db_file: &str, user_id: u64) -> Self {
-        let mut user = User::from_file(db_file, user_id).unwrap();
-        user.pending = Records(
-            user.pending
-                .into_iter()
-                .filter(|record| match record.2 {
-                    Some(timestamp) => {
-                        let days = DateTime::<Utc>::from_utc(
-                            NaiveDateTime::from_timestamp(timestamp, 0),
-                            Utc,
-                        );
-                        days.num_days_from_ce() == get_today().num_days_from_ce()
-                    }
-                    None => true,
-                })
-                .collect::<Vec<_>>(),
-        );
+    pub fn new(
+        store: &Arc<dyn UserStore>,
+        user_id: u64,
+        penalties: &PenaltyConfig,
+        tag: Option<String>,
+    ) -> Self {
+        let mut pending = Self {
+            store: store.clone(),
+            user_id,
+            pending: Vec::new(),
+            penalty: penalties.overdue,
+            penalized: Vec::new(),
+            tag,
+            settings: Settings::default(),
+        };
+        pending.refresh();
+        pending
+    }
 
-        Self {
-            db_file: db_file.to_owned(),
-            user,
+    /// Take penalties for tasks that went overdue, then fetch the tasks again, most urgent
+    /// first: by priority, then by due date with the ones without one last, then oldest first.
+    /// Completed ones come last.
+    fn refresh(&mut self) {
+        self.settings = self
+            .store
+            .settings(self.user_id)
+            .expect("Cannot fetch settings for user");
+        if self.penalty > 0 {
+            let penalized = self
+                .store
+                .penalize_overdue(Origin::system(Surface::Penalty), self.user_id, self.penalty)
+                .expect("Cannot penalize overdue tasks for user");
+            self.penalized.extend(penalized);
         }
+
+        let pending = self
+            .store
+            .tasks(self.user_id, TaskKind::Pending)
+            .expect("Cannot fetch pending tasks for user")
+            .into_iter()
+            .filter(|task| self.tag.as_ref().is_none_or(|tag| task.tags.contains(tag)))
+            .collect();
+        self.pending = Self::without_stale(pending, &self.settings);
+        self.pending.sort_by_key(|task| {
+            (
+                task.completed_at.is_some(),
+                Reverse(task.priority),
+                task.due_at.is_none(),
+                task.due_at,
+                task.created_at,
+                task.id,
+            )
+        });
     }
 
-    fn complete_task(&mut self, task_name: &str) -> Option<()> {
-        let record = self
-            .user
-            .pending
-            .iter_mut()
-            .filter(|record| record.0 == task_name)
-            .next();
-        if let Some(record) = record {
-            record.2 = Some(DateTime::timestamp(&Utc::now()));
-            self.user
-                .transactions
-                .push(record.0.to_owned(), record.1, record.2);
-            self.user
-                .update(&self.db_file)
-                .expect("Cannot update db for user");
-            Some(())
-        } else {
-            None
-        }
+    /// Drop tasks that were completed before today
+    fn without_stale(pending: Vec<Task>, settings: &Settings) -> Vec<Task> {
+        pending
+            .into_iter()
+            .filter(|task| match task.completed_at {
+                Some(timestamp) => settings.day_of(timestamp) == get_today(settings),
+                None => true,
+            })
+            .collect()
+    }
+
+    fn complete_task(&mut self, id: RecordId) -> Option<()> {
+        let completed = self
+            .store
+            .complete_task(
+                Origin::user(self.user_id, Surface::Pending),
+                self.user_id,
+                TaskKind::Pending,
+                id,
+                &StreakBonuses::default(),
+            )
+            .expect("Cannot update db for user");
+        self.refresh();
+        completed.map(|_| ())
+    }
+
+    /// Check off an item of a task's checklist
+    fn check_item(&mut self, id: RecordId, item: usize) -> Option<()> {
+        let checked = self
+            .store
+            .check_item(
+                Origin::user(self.user_id, Surface::Pending),
+                self.user_id,
+                TaskKind::Pending,
+                id,
+                item,
+                &StreakBonuses::default(),
+            )
+            .expect("Cannot update db for user");
+        self.refresh();
+        checked.map(|_| ())
+    }
+
+    /// Log progress towards a task's target. Progress on pending tasks never expires.
+    fn add_progress(&mut self, id: RecordId, amount: u32) -> Option<()> {
+        let progressed = self
+            .store
+            .add_progress(
+                Origin::user(self.user_id, Surface::Pending),
+                self.user_id,
+                TaskKind::Pending,
+                id,
+                amount,
+                &StreakBonuses::default(),
+            )
+            .expect("Cannot update db for user");
+        self.refresh();
+        progressed.map(|_| ())
     }
 
     fn delegate_interaction_response<'a>(
         &self,
         interaction: &'a mut CreateInteractionResponse,
     ) -> &'a mut CreateInteractionResponse {
-        if self.user.pending.len() == 0 {
+        if self.pending.len() == 0 {
             return interaction.interaction_response_data(|data| {
                 data.create_embed(|embed| {
                     embed
@@ -86,12 +180,23 @@ impl Pending {
         let mut tasks = String::new();
         let mut rewards = String::new();
         let mut when = String::new();
+        let penalized = match self.penalized.iter().map(|penalty| -penalty.amount).sum() {
+            0 => String::new(),
+            lost => format!("You lost :coin:x{} to overdue tasks :<", lost),
+        };
+
         let mut completed = 0;
+        let now = Utc::now().timestamp();
 
-        for record in &self.user.pending {
-            tasks.push_str(&format!("{}\n", record.0));
-            rewards.push_str(&format!(":coin:x{}\n", record.1));
-            when.push_str(&match record.2 {
+        for task in &self.pending {
+            tasks.push_str(&format!(
+                "`#{}` {}{}\n",
+                task.id,
+                task.priority.badge(),
+                task.title
+            ));
+            rewards.push_str(&format!(":coin:x{}\n", task.points));
+            when.push_str(&match task.completed_at {
                 Some(timestamp) => {
                     completed += 1;
                     let timestamp = DateTime::timestamp(&Utc::now()) - timestamp as i64;
@@ -102,29 +207,73 @@ impl Pending {
                         timestamp % 3600 % 60
                     )
                 }
-                None => "⌛ Not Completed\n".to_owned(),
+                None => {
+                    let due = match task.due_at {
+                        Some(due_at) if due_at < now => {
+                            format!("🔴 Overdue by *{}*", span(now - due_at))
+                        }
+                        Some(due_at) => format!("⏰ Due in *{}*", span(due_at - now)),
+                        None => "⌛ Not Completed".to_owned(),
+                    };
+                    match (task.checklist.is_empty(), task.goal()) {
+                        (true, Some(target)) => {
+                            format!("{} {}\n", progress_bar(task.progress, target), due)
+                        }
+                        (false, Some(items)) => {
+                            format!("{} {}\n", progress_bar(task.checked(), items), due)
+                        }
+                        (_, None) => due + "\n",
+                    }
+                }
             })
         }
 
-        let completed = completed as f32 / self.user.pending.len() as f32;
+        let checklists = self
+            .pending
+            .iter()
+            .filter(|task| task.completed_at.is_none() && !task.checklist.is_empty())
+            .map(|task| {
+                let items = task
+                    .checklist
+                    .iter()
+                    .map(|item| match item.done_at {
+                        Some(_) => format!("☑ ~~{}~~", item.title),
+                        None => format!("☐ {}", item.title),
+                    })
+                    .collect::<Vec<_>>();
+                format!("`#{}` {}: {}\n", task.id, task.title, items.join(" · "))
+            })
+            .collect::<String>();
+
+        let completed = completed as f32 / self.pending.len() as f32;
+        let color = if self.pending.iter().any(|task| task.is_overdue(now)) {
+            Color::DARK_RED
+        } else {
+            Color::from_rgb(
+                lerp(&227, &174, &completed),
+                lerp(&36, &243, &completed),
+                lerp(&43, &89, &completed),
+            )
+        };
 
         interaction.interaction_response_data(|data| {
             data.create_embed(|embed| {
                 embed
                     .title(format!(
-                        "Pending tasks! :D ({}% completed)",
+                        "Pending tasks{}! :D ({}% completed)",
+                        self.tag
+                            .as_ref()
+                            .map_or_else(String::new, |tag| format!(" tagged `{}`", tag)),
                         (completed * 100_f32) as u64
                     ))
+                    .description(penalized)
                     .field("Task", tasks, true)
                     .field("Rewards", rewards, true)
                     .field("Progress", when, true)
-                    .color(Color::from_rgb(
-                        lerp(&227, &174, &completed),
-                        lerp(&36, &243, &completed),
-                        lerp(&43, &89, &completed),
-                    ))
+                    .fields((!checklists.is_empty()).then_some(("Checklists", checklists, false)))
+                    .color(color)
                     .footer(|footer| {
-                        let elapsed = (get_tomorrow() - Utc::now()).num_seconds();
+                        let elapsed = (get_tomorrow(&self.settings) - Utc::now()).num_seconds();
                         footer.text(format!(
                             "{}h {}m {}s until refresh",
                             elapsed / 3600,
@@ -134,29 +283,105 @@ impl Pending {
                     })
             })
             .components(|components| {
-                if self.user.pending.iter().any(|record| record.2.is_none()) {
+                let open = self
+                    .pending
+                    .iter()
+                    .filter(|task| task.completed_at.is_none() && task.goal().is_none())
+                    .take(25)
+                    .collect::<Vec<_>>();
+                if !open.is_empty() {
                     components.create_action_row(|row| {
                         row.create_select_menu(|menu| {
                             menu.options(|options| {
-                                for record in &self.user.pending {
-                                    if record.2.is_none() {
-                                        options.create_option(|option| {
-                                            option
-                                                .label(&record.0)
-                                                .description(&format!("{}x coins", record.1))
-                                                .value(&record.0)
-                                        });
-                                    }
+                                for task in &open {
+                                    options.create_option(|option| {
+                                        option
+                                            .label(format!(
+                                                "{}{}",
+                                                task.priority.badge(),
+                                                task.title
+                                            ))
+                                            .description(&format!("{}x coins", task.points))
+                                            .value(task.id)
+                                    });
                                 }
                                 options
                             })
                             .placeholder("Pick your poison :>")
                             .custom_id("complete_daily_menu")
                         })
+                    });
+                }
+
+                let steps = self
+                    .pending
+                    .iter()
+                    .filter(|task| task.completed_at.is_none() && task.checklist.is_empty())
+                    .filter_map(|task| Some((task, task.target?)))
+                    .flat_map(|(task, target)| {
+                        progress_steps(task.progress, target)
+                            .into_iter()
+                            .map(move |step| (task, target, step))
+                    })
+                    .take(25)
+                    .collect::<Vec<_>>();
+                if !steps.is_empty() {
+                    components.create_action_row(|row| {
+                        row.create_select_menu(|menu| {
+                            menu.options(|options| {
+                                for (task, target, step) in &steps {
+                                    options.create_option(|option| {
+                                        option
+                                            .label(format!("{} +{}", task.title, step))
+                                            .description(format!(
+                                                "{}/{} after this",
+                                                task.progress + step,
+                                                target
+                                            ))
+                                            .value(format!("{}:{}", task.id, step))
+                                    });
+                                }
+                                options
+                            })
+                            .placeholder("Log some progress :>")
+                            .custom_id("log_progress_menu")
+                        })
+                    });
+                }
+
+                let items = self
+                    .pending
+                    .iter()
+                    .filter(|task| task.completed_at.is_none())
+                    .flat_map(|task| {
+                        task.checklist
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, item)| item.done_at.is_none())
+                            .map(move |(index, item)| (task, index, item))
                     })
-                } else {
-                    components
+                    .take(25)
+                    .collect::<Vec<_>>();
+                if !items.is_empty() {
+                    components.create_action_row(|row| {
+                        row.create_select_menu(|menu| {
+                            menu.options(|options| {
+                                for (task, index, item) in &items {
+                                    options.create_option(|option| {
+                                        option
+                                            .label(&item.title)
+                                            .description(format!("#{} {}", task.id, task.title))
+                                            .value(format!("{}:{}", task.id, index))
+                                    });
+                                }
+                                options
+                            })
+                            .placeholder("Check something off :>")
+                            .custom_id("check_item_menu")
+                        })
+                    });
                 }
+                components
             });
             data
         })
@@ -179,14 +404,32 @@ impl Pending {
             .await?
             .await_component_interactions(shard_messenger)
             .timeout(Duration::from_secs(15))
-            .author_id(self.user.id)
+            .author_id(self.user_id)
             .await;
 
         let daily = &Arc::new(Mutex::new(self));
         collector
             .for_each(|interaction| async move {
                 let mut daily = daily.lock().await;
-                daily.complete_task(&interaction.data.values[0]);
+                let value = &interaction.data.values[0];
+                let mut completed = None;
+                // Progress and checklist items are picked as `id:amount` and `id:item`
+                let pair: Option<(RecordId, u32)> = value
+                    .split_once(':')
+                    .and_then(|(id, n)| Some((id.parse().ok()?, n.parse().ok()?)));
+                match (interaction.data.custom_id.as_str(), pair) {
+                    ("log_progress_menu", Some((id, amount))) => {
+                        daily.add_progress(id, amount);
+                    }
+                    ("check_item_menu", Some((id, item))) => {
+                        daily.check_item(id, item as usize);
+                    }
+                    _ => {
+                        if let Ok(id) = value.parse() {
+                            completed = daily.complete_task(id).map(|_| id);
+                        }
+                    }
+                }
                 interaction
                     .create_interaction_response(http, |interaction| {
                         daily
@@ -195,6 +438,23 @@ impl Pending {
                     })
                     .await
                     .expect("Unable to update interaction");
+
+                if let Some(id) = completed {
+                    let proof = ask_for_proof(
+                        http.clone(),
+                        shard_messenger.clone(),
+                        interaction.clone(),
+                        daily.store.clone(),
+                        Surface::Pending,
+                        TaskKind::Pending,
+                        id,
+                    );
+                    tokio::spawn(async move {
+                        if let Err(e) = proof.await {
+                            println!("Cannot collect proof: {}", e);
+                        }
+                    });
+                }
             })
             .await;
 
@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use serenity::{
+    builder::CreateApplicationCommand,
+    http::Http,
+    model::interactions::application_command::{
+        ApplicationCommandInteraction,
+        ApplicationCommandInteractionDataOptionValue as ApplicationValue,
+        ApplicationCommandOptionType,
+    },
+};
+
+use crate::util::{Settings, UserStore};
+
+/// Show or change the user's timezone and when their day starts
+pub struct Preferences<'a> {
+    store: &'a dyn UserStore,
+    user_id: u64,
+}
+
+impl<'a> Preferences<'a> {
+    pub fn new(store: &'a dyn UserStore, user_id: u64) -> Self {
+        Self { store, user_id }
+    }
+
+    pub fn create_application_command() -> CreateApplicationCommand {
+        let mut command = CreateApplicationCommand::default();
+        command
+            .name("settings")
+            .description("Pick your timezone and when your day starts!")
+            .create_option(|option| {
+                option
+                    .name("timezone")
+                    .description("Your timezone, e.g. Europe/Berlin, or an offset from UTC like +07:00 or UTC-5")
+                    .kind(ApplicationCommandOptionType::String)
+            })
+            .create_option(|option| {
+                option
+                    .name("day_start")
+                    .description("The hour your day starts at, e.g. 4 if you stay up late")
+                    .kind(ApplicationCommandOptionType::Integer)
+                    .min_int_value(0)
+                    .max_int_value(23)
+            });
+        command
+    }
+
+    /// Apply the options given to the user's settings, or explain what is wrong with them
+    fn apply(&self, interaction: &ApplicationCommandInteraction) -> Result<Settings, String> {
+        let mut settings = self
+            .store
+            .settings(self.user_id)
+            .expect("Cannot fetch settings for user");
+        let previous = settings;
+
+        for option in &interaction.data.options {
+            match (option.name.as_str(), &option.resolved) {
+                ("timezone", Some(ApplicationValue::String(timezone))) => {
+                    if let Some(named) = Settings::parse_timezone(timezone) {
+                        settings.timezone = Some(named);
+                    } else {
+                        settings.utc_offset = Settings::parse_offset(timezone).ok_or_else(|| {
+                            format!(
+                                "I don't understand `{}` :< Try `Europe/Berlin`, `+07:00` or `UTC-5`",
+                                timezone
+                            )
+                        })?;
+                        settings.timezone = None;
+                    }
+                }
+                ("day_start", Some(ApplicationValue::Integer(hour))) => {
+                    settings.day_start = u32::try_from(*hour)
+                        .ok()
+                        .filter(|hour| *hour < 24)
+                        .ok_or_else(|| {
+                            "Days have to start at an hour from 0 to 23 :<".to_owned()
+                        })?;
+                }
+                _ => {}
+            }
+        }
+
+        if settings != previous {
+            self.store
+                .update_settings(self.user_id, settings)
+                .expect("Cannot update settings for user");
+        }
+        Ok(settings)
+    }
+
+    pub async fn handle_interaction(
+        &mut self,
+        http: &Arc<Http>,
+        interaction: ApplicationCommandInteraction,
+    ) -> Result<(), serenity::Error> {
+        let response = match self.apply(&interaction) {
+            Ok(settings) => format!("Your days follow {} :D", settings),
+            Err(error) => error,
+        };
+
+        interaction
+            .create_interaction_response(http, |reply| {
+                reply.interaction_response_data(|data| data.content(response))
+            })
+            .await?;
+
+        Ok(())
+    }
+}
@@ -1,29 +1,631 @@
-use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
 
-pub struct Shop(Vec<Item>);
+use chrono::NaiveDate;
+use serenity::{
+    async_trait,
+    builder::{
+        CreateApplicationCommand, CreateApplicationCommandOption, CreateComponents, CreateEmbed,
+        CreateInteractionResponse,
+    },
+    client::bridge::gateway::ShardMessenger,
+    http::Http,
+    model::interactions::{
+        application_command::{
+            ApplicationCommandInteraction, ApplicationCommandInteractionDataOption,
+            ApplicationCommandInteractionDataOptionValue as ApplicationValue,
+            ApplicationCommandOptionType,
+        },
+        message_component::{ButtonStyle, ComponentType, MessageComponentInteraction},
+        InteractionResponseType,
+    },
+};
 
-#[derive(Serialize, Deserialize)]
-struct Item {
-    name: String,
-    cost: u64,
-    qty: i64,
+use crate::util::{
+    catalog::{name_key, Catalog, Item, Purchase, Restock},
+    Component, ComponentManager, Origin, Settings, Surface, Transaction, UserStore,
+};
+
+/// The reward shop, selling whatever the catalog lists at the time along with the items each
+/// user added for themselves
+pub struct Shop {
+    store: Arc<dyn UserStore>,
+    catalog: Arc<RwLock<Catalog>>,
+    user_id: u64,
+}
+
+const CHUNK_SIZE: usize = 10;
+
+/// The options describing an item, shared by `/shop add` and `/shop edit`
+fn item_options(option: &mut CreateApplicationCommandOption, required: bool) {
+    option
+        .create_sub_option(|option| {
+            option
+                .name("cost")
+                .description("How many coins it costs")
+                .kind(ApplicationCommandOptionType::Integer)
+                .min_int_value(1)
+                .required(required)
+        })
+        .create_sub_option(|option| {
+            option
+                .name("qty")
+                .description("How many you can buy, -1 for no limit")
+                .kind(ApplicationCommandOptionType::Integer)
+                .min_int_value(-1)
+        })
+        .create_sub_option(|option| {
+            option
+                .name("restock")
+                .description("When the stock refills: daily, weekly, monthly or every 3 days")
+                .kind(ApplicationCommandOptionType::String)
+        })
+        .create_sub_option(|option| {
+            option
+                .name("available_from")
+                .description("The first day it is for sale, e.g. 2026-12-01")
+                .kind(ApplicationCommandOptionType::String)
+        })
+        .create_sub_option(|option| {
+            option
+                .name("available_until")
+                .description("The last day it is for sale, e.g. 2026-12-31")
+                .kind(ApplicationCommandOptionType::String)
+        })
+        .create_sub_option(|option| {
+            option
+                .name("description")
+                .description("What the reward is")
+                .kind(ApplicationCommandOptionType::String)
+        });
+}
+
+/// Apply the options given to an item, or explain what is wrong with them. Text options
+/// are cleared with `none`.
+fn apply_options(
+    item: &mut Item,
+    options: &[ApplicationCommandInteractionDataOption],
+) -> Result<(), String> {
+    let date = |date: &str| {
+        NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+            .map_err(|_| format!("I don't understand `{}` :< Try `2026-12-31`", date))
+    };
+
+    for option in options {
+        let text = match &option.resolved {
+            Some(ApplicationValue::String(text)) if text.trim() == "none" => None,
+            Some(ApplicationValue::String(text)) => Some(text.as_str()),
+            _ => None,
+        };
+        match (option.name.as_str(), &option.resolved) {
+            ("new_name", Some(ApplicationValue::String(name))) => {
+                item.name = name.trim().to_owned()
+            }
+            ("cost", Some(ApplicationValue::Integer(cost))) => item.cost = *cost,
+            ("qty", Some(ApplicationValue::Integer(qty))) => {
+                item.qty = (*qty >= 0).then_some(*qty)
+            }
+            ("restock", _) => {
+                item.restock = text
+                    .map(|restock| {
+                        Restock::parse(restock).ok_or_else(|| {
+                            format!(
+                                "I don't understand `{}` :< Try `daily`, `weekly`, `monthly` or `every 3 days`",
+                                restock
+                            )
+                        })
+                    })
+                    .transpose()?
+            }
+            ("available_from", _) => item.available_from = text.map(date).transpose()?,
+            ("available_until", _) => item.available_until = text.map(date).transpose()?,
+            ("description", _) => item.description = text.map(str::to_owned),
+            _ => {}
+        }
+    }
+
+    item.validate()
+        .map_err(|reason| format!("That can't be sold: {} :<", reason))
 }
 
 impl Shop {
-    pub fn new() -> Self {
-        Self(Vec::new())
+    pub fn new(store: &Arc<dyn UserStore>, catalog: &Arc<RwLock<Catalog>>, user_id: u64) -> Self {
+        Self {
+            store: store.clone(),
+            catalog: catalog.clone(),
+            user_id,
+        }
+    }
+
+    pub fn create_application_command() -> CreateApplicationCommand {
+        let mut command = CreateApplicationCommand::default();
+        command
+            .name("shop")
+            .description("Spend your coins on something nice :D")
+            .create_option(|option| {
+                option
+                    .name("browse")
+                    .description("See what's for sale and buy it!")
+                    .kind(ApplicationCommandOptionType::SubCommand)
+            })
+            .create_option(|option| {
+                option
+                    .name("add")
+                    .description("Add a reward of your own to the shop")
+                    .kind(ApplicationCommandOptionType::SubCommand)
+                    .create_sub_option(|option| {
+                        option
+                            .name("name")
+                            .description("What the reward is called")
+                            .kind(ApplicationCommandOptionType::String)
+                            .required(true)
+                    });
+                item_options(option, true);
+                option
+            })
+            .create_option(|option| {
+                option
+                    .name("edit")
+                    .description("Change one of your own rewards")
+                    .kind(ApplicationCommandOptionType::SubCommand)
+                    .create_sub_option(|option| {
+                        option
+                            .name("name")
+                            .description("The reward to change")
+                            .kind(ApplicationCommandOptionType::String)
+                            .required(true)
+                    })
+                    .create_sub_option(|option| {
+                        option
+                            .name("new_name")
+                            .description("What to call it instead")
+                            .kind(ApplicationCommandOptionType::String)
+                    });
+                item_options(option, false);
+                option
+            })
+            .create_option(|option| {
+                option
+                    .name("remove")
+                    .description("Take one of your own rewards out of the shop")
+                    .kind(ApplicationCommandOptionType::SubCommand)
+                    .create_sub_option(|option| {
+                        option
+                            .name("name")
+                            .description("The reward to remove")
+                            .kind(ApplicationCommandOptionType::String)
+                            .required(true)
+                    })
+            });
+        command
+    }
+
+    /// Whether the global catalog sells an item with this name
+    fn in_catalog(&self, name: &str) -> bool {
+        self.catalog
+            .read()
+            .expect("Shop catalog is poisoned")
+            .item(name)
+            .is_some()
+    }
+
+    /// Add, edit or remove one of the user's own items, and say how it went
+    fn manage(&self, subcommand: &ApplicationCommandInteractionDataOption) -> String {
+        let name = subcommand
+            .options
+            .iter()
+            .find(|option| option.name == "name")
+            .and_then(|option| match &option.resolved {
+                Some(ApplicationValue::String(name)) => Some(name.trim().to_owned()),
+                _ => None,
+            })
+            .unwrap_or_default();
+        let own = self
+            .store
+            .shop_items(self.user_id)
+            .expect("Cannot fetch shop items for user")
+            .into_iter()
+            .find(|item| name_key(&item.name) == name_key(&name));
+
+        match subcommand.name.as_str() {
+            "add" => {
+                if own.is_some() || self.in_catalog(&name) {
+                    return format!("The shop already sells **{}** :<", name);
+                }
+                let mut item = Item {
+                    name,
+                    cost: 0,
+                    qty: None,
+                    description: None,
+                    restock: None,
+                    available_from: None,
+                    available_until: None,
+                    owner: Some(self.user_id),
+                };
+                if let Err(error) = apply_options(&mut item, &subcommand.options) {
+                    return error;
+                }
+                if self
+                    .store
+                    .insert_shop_item(self.user_id, &item)
+                    .expect("Cannot add shop item for user")
+                {
+                    format!("**{}** is in your shop now! :D", item.name)
+                } else {
+                    format!("You already have a reward called **{}** :<", item.name)
+                }
+            }
+            "edit" => {
+                let mut item = match own {
+                    Some(item) => item,
+                    None => return format!("You don't have a reward called **{}** :<", name),
+                };
+                if let Err(error) = apply_options(&mut item, &subcommand.options) {
+                    return error;
+                }
+                if name_key(&item.name) != name_key(&name) && self.in_catalog(&item.name) {
+                    return format!("The shop already sells **{}** :<", item.name);
+                }
+                if self
+                    .store
+                    .update_shop_item(self.user_id, &name, &item)
+                    .expect("Cannot update shop item for user")
+                {
+                    format!("**{}** has been changed!", item.name)
+                } else {
+                    format!("You already have a reward called **{}** :<", item.name)
+                }
+            }
+            "remove" => {
+                if self
+                    .store
+                    .delete_shop_item(self.user_id, &name)
+                    .expect("Cannot remove shop item for user")
+                {
+                    format!("**{}** is out of your shop", name)
+                } else {
+                    format!("You don't have a reward called **{}** :<", name)
+                }
+            }
+            _ => panic!("Unknown shop subcommand!"),
+        }
+    }
+
+    pub async fn handle_interaction(
+        &mut self,
+        http: &Arc<Http>,
+        interaction: ApplicationCommandInteraction,
+        shard: &ShardMessenger,
+    ) -> Result<(), serenity::Error> {
+        let subcommand = interaction.data.options.first().unwrap();
+        if subcommand.name != "browse" {
+            let response = self.manage(subcommand);
+            interaction
+                .create_interaction_response(http, |reply| {
+                    reply.interaction_response_data(|data| data.content(response))
+                })
+                .await?;
+            return Ok(());
+        }
+
+        let mut component_mgr = ComponentManager::new();
+        component_mgr
+            .add_component(Box::new(Page::new(
+                self.store.clone(),
+                self.catalog.clone(),
+                self.user_id,
+            )))
+            .await;
+        component_mgr
+            .handle_interaction(http, interaction, shard, 60)
+            .await?;
+        Ok(())
+    }
+}
+
+struct Page {
+    store: Arc<dyn UserStore>,
+    /// Read on every render, so a reloaded catalog shows up on the next click
+    catalog: Arc<RwLock<Catalog>>,
+    user_id: u64,
+    /// Limited-time items come and go with the user's days
+    settings: Settings,
+    index: usize,
+    /// What came of the last purchase
+    notice: Option<String>,
+}
+
+impl Page {
+    fn new(store: Arc<dyn UserStore>, catalog: Arc<RwLock<Catalog>>, user_id: u64) -> Self {
+        Self {
+            settings: store
+                .settings(user_id)
+                .expect("Cannot fetch settings for user"),
+            store,
+            catalog,
+            user_id,
+            index: 0,
+            notice: None,
+        }
     }
-    /*
 
-    pub fn from_file(shop_file: &str) -> Result<(), csv::Error> {
-        let mut shop = Shop::new();
-        let mut rdr = Reader::from_path(shop_file)?;
+    /// Every item for sale today, the user's own after the catalog's
+    fn for_sale(&self) -> Vec<Item> {
+        let today = self.settings.today();
+        let personal = self
+            .store
+            .shop_items(self.user_id)
+            .expect("Cannot fetch shop items for user");
+        self.catalog
+            .read()
+            .expect("Shop catalog is poisoned")
+            .merged(&personal)
+            .into_iter()
+            .filter(|item| item.is_available(today))
+            .collect()
+    }
+
+    /// The items on the current page
+    fn items(&self) -> Vec<Item> {
+        self.for_sale()
+            .into_iter()
+            .skip(self.index * CHUNK_SIZE)
+            .take(CHUNK_SIZE)
+            .collect()
+    }
+
+    fn max_page(&self) -> usize {
+        self.for_sale().len().div_ceil(CHUNK_SIZE)
+    }
+
+    /// How many of an item are left, `None` if there is no limit
+    fn stock(&self, item: &Item) -> Option<i64> {
+        item.stock(
+            self.store
+                .sold(self.user_id, item)
+                .expect("Cannot fetch stock for user"),
+        )
+    }
+
+    /// An item's price, how many are left and for how long, e.g. ":coin:x50 · 2 left"
+    fn price(&self, item: &Item, stock: Option<i64>) -> String {
+        let mut price = format!(":coin:x{}", item.cost);
+        match (stock, item.restock) {
+            (Some(0), Some(restock)) => price += &format!(" · Sold out, restocks {}", restock),
+            (Some(0), None) => price += " · Sold out",
+            (Some(stock), _) => price += &format!(" · {} left", stock),
+            (None, _) => {}
+        }
+        if let Some(until) = item.available_until {
+            price += &format!("\nUntil {}", until.format("%m/%d/%Y"));
+        }
+        price
+    }
+
+    fn buy(&mut self, name: &str) {
+        let item = self
+            .for_sale()
+            .into_iter()
+            .find(|item| name_key(&item.name) == name_key(name));
+        let item = match item {
+            Some(item) => item,
+            None => {
+                self.notice = Some(format!("Sorry, **{}** is no longer for sale :<", name));
+                return;
+            }
+        };
+
+        let purchase = self
+            .store
+            .buy(
+                Origin::user(self.user_id, Surface::Shop),
+                self.user_id,
+                &item,
+            )
+            .expect("Cannot buy item for user");
+        self.notice = Some(match purchase {
+            Purchase::Bought { balance } => format!(
+                "You bought **{}** for :coin:x{}! You have :coin:x{} left :D",
+                item.name, item.cost, balance
+            ),
+            Purchase::TooExpensive { balance } => format!(
+                "**{}** costs :coin:x{}, but you only have :coin:x{} :<",
+                item.name, item.cost, balance
+            ),
+            Purchase::SoldOut => format!("Sorry, **{}** is sold out :<", item.name),
+            Purchase::Unavailable => {
+                format!("Sorry, **{}** is no longer for sale :<", item.name)
+            }
+        });
+    }
+
+    fn get_embed(
+        &self,
+        items: &[(Item, Option<i64>)],
+        transactions: &[Transaction],
+    ) -> CreateEmbed {
+        let mut embed = CreateEmbed::default();
+        if !items.is_empty() {
+            let mut description = String::new();
+            if let Some(notice) = &self.notice {
+                description += notice;
+                description += "\n\n";
+            }
+            let balance = transactions
+                .iter()
+                .map(|transaction| transaction.amount)
+                .sum::<i64>();
+            description += &format!("You have :coin:x{}", balance);
+
+            embed.title("Reward shop! :D").description(description);
+            for (item, stock) in items {
+                let name = match stock {
+                    Some(0) => format!("~~{}~~", item.name),
+                    _ => item.name.clone(),
+                };
+                embed.field(
+                    name,
+                    format!(
+                        "{}\n{}",
+                        self.price(item, *stock),
+                        item.description.as_deref().unwrap_or("\u{200b}")
+                    ),
+                    true,
+                );
+            }
+        } else {
+            embed
+                .title("The shop is closed :<")
+                .description("There is nothing for sale right now, check back later!");
+        }
+
+        embed
+    }
+
+    fn delegate_component<'a>(
+        &self,
+        component: &'a mut CreateComponents,
+        items: &[(Item, Option<i64>)],
+    ) -> &'a mut CreateComponents {
+        if items.is_empty() {
+            return component;
+        }
+
+        let in_stock = items
+            .iter()
+            .filter(|(_, stock)| *stock != Some(0))
+            .map(|(item, _)| item)
+            .collect::<Vec<_>>();
+        let max_page = self.max_page();
+        component
+            .create_action_row(|row| {
+                row.create_select_menu(|menu| {
+                    if in_stock.is_empty() {
+                        // A menu needs an option even when it is disabled
+                        return menu
+                            .options(|options| {
+                                options.create_option(|option| {
+                                    option.label("Sold out").value("sold_out")
+                                })
+                            })
+                            .placeholder("Everything here is sold out :<")
+                            .custom_id("buy_item_menu")
+                            .disabled(true);
+                    }
+                    menu.options(|options| {
+                        for item in &in_stock {
+                            options.create_option(|option| {
+                                option
+                                    .label(&item.name)
+                                    .description(format!("{}x coins", item.cost))
+                                    .value(&item.name)
+                            });
+                        }
+                        options
+                    })
+                    .placeholder("Treat yourself :>")
+                    .custom_id("buy_item_menu")
+                })
+            })
+            .create_action_row(|row| {
+                row.create_button(|button| {
+                    button
+                        .label("⬅️")
+                        .custom_id("left_page_select")
+                        .style(ButtonStyle::Primary)
+                        .disabled(self.index == 0)
+                })
+                .create_button(|button| {
+                    button
+                        .label(format!("Page {}/{}", self.index + 1, max_page))
+                        .custom_id("page_display")
+                        .style(ButtonStyle::Secondary)
+                        .disabled(true)
+                })
+                .create_button(|button| {
+                    button
+                        .label("➡️")
+                        .custom_id("right_page_select")
+                        .style(ButtonStyle::Primary)
+                        .disabled(self.index + 1 >= max_page)
+                })
+            })
+    }
+}
+
+#[async_trait]
+impl Component for Page {
+    fn want_component_interaction(&self, component_interaction_type: ComponentType) -> bool {
+        component_interaction_type == ComponentType::Button
+            || component_interaction_type == ComponentType::SelectMenu
+    }
+
+    fn delegate_response<'a>(
+        &self,
+        response: &'a mut CreateInteractionResponse,
+    ) -> &'a mut CreateInteractionResponse {
+        let items = self
+            .items()
+            .into_iter()
+            .map(|item| {
+                let stock = self.stock(&item);
+                (item, stock)
+            })
+            .collect::<Vec<_>>();
+        let transactions = self
+            .store
+            .transactions(self.user_id)
+            .expect("Cannot fetch transactions for user");
+        response.interaction_response_data(|data| {
+            data.add_embed(self.get_embed(&items, &transactions))
+                .components(|component| self.delegate_component(component, &items))
+        })
+    }
+
+    async fn on_interaction(
+        &mut self,
+        http: &Arc<Http>,
+        interaction: &Arc<MessageComponentInteraction>,
+    ) -> Result<(), serenity::Error> {
+        // Anyone can click a shop in a channel, but only its owner can spend their coins
+        if interaction.user.id.0 != self.user_id {
+            interaction
+                .create_interaction_response(http, |response| {
+                    response.interaction_response_data(|data| {
+                        data.content("This isn't your shop, open your own with /shop browse :>")
+                            .ephemeral(true)
+                    })
+                })
+                .await?;
+            return Ok(());
+        }
 
-        for record in rdr.deserialize() {
-            shop.0.push(record?);
+        match (
+            interaction.data.component_type,
+            interaction.data.custom_id.as_str(),
+        ) {
+            (ComponentType::Button, "left_page_select") => {
+                self.index = self.index.saturating_sub(1)
+            }
+            (ComponentType::Button, "right_page_select") => self.index += 1,
+            (ComponentType::SelectMenu, "buy_item_menu") => {
+                let name = interaction.data.values[0].clone();
+                self.buy(&name);
+            }
+            (kind, custom_id) => panic!(
+                "Unexpectedly received an interaction of type {} from {}\n",
+                kind as u8, custom_id
+            ),
         }
+        // The catalog may have shrunk since the page was shown
+        self.index = self.index.min(self.max_page().saturating_sub(1));
 
+        interaction
+            .create_interaction_response(http, |response| {
+                self.delegate_response(response)
+                    .kind(InteractionResponseType::UpdateMessage)
+            })
+            .await
+            .expect("Unable to update interaction");
         Ok(())
     }
-    */
 }
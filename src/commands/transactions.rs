@@ -1,5 +1,4 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use serenity::{
     async_trait,
@@ -13,18 +12,39 @@ use serenity::{
     },
 };
 
-use crate::util::{db::User, Component, ComponentManager, Records};
+use super::util::breakdown;
+use crate::util::{
+    Component, ComponentManager, Proof, RecordId, Settings, Source, Transaction, UserStore,
+};
 
 pub struct Transactions(ComponentManager);
 
 const CHUNK_SIZE: usize = 10;
 
 impl Transactions {
-    pub async fn new(db_file: &str, user_id: u64) -> Self {
-        let user = User::from_file(db_file, user_id).unwrap();
+    /// The user's transactions, only those tagged `tag` if there is one
+    pub async fn new(store: &Arc<dyn UserStore>, user_id: u64, tag: Option<String>) -> Self {
+        let transactions = store
+            .transactions(user_id)
+            .expect("Cannot fetch transactions for user")
+            .into_iter()
+            .filter(|transaction| {
+                tag.as_ref()
+                    .is_none_or(|tag| transaction.tags.contains(tag))
+            })
+            .collect();
+        let settings = store
+            .settings(user_id)
+            .expect("Cannot fetch settings for user");
+        let proofs = store
+            .proofs(user_id)
+            .expect("Cannot fetch proofs for user")
+            .into_iter()
+            .map(|(task_id, completed, proof)| ((task_id, completed), proof))
+            .collect();
         let mut component_mgr = ComponentManager::new();
         component_mgr
-            .add_component(Box::new(Page::new(user.transactions)))
+            .add_component(Box::new(Page::new(transactions, tag, settings, proofs)))
             .await;
         Self(component_mgr)
     }
@@ -43,13 +63,53 @@ impl Transactions {
 }
 
 struct Page {
-    records: Records,
+    records: Vec<Transaction>,
     index: usize,
+    tag: Option<String>,
+    /// Coins per tag across every page
+    breakdown: String,
+    /// Dates are shown in the user's timezone
+    settings: Settings,
+    /// What was attached to each completion, by task and completion
+    proofs: HashMap<(RecordId, i64), Proof>,
 }
 
 impl Page {
-    fn new(records: Records) -> Self {
-        Self { records, index: 0 }
+    fn new(
+        records: Vec<Transaction>,
+        tag: Option<String>,
+        settings: Settings,
+        proofs: HashMap<(RecordId, i64), Proof>,
+    ) -> Self {
+        Self {
+            breakdown: breakdown(&records),
+            records,
+            index: 0,
+            tag,
+            settings,
+            proofs,
+        }
+    }
+
+    /// The proof attached to the completion that paid a transaction, with long notes cut short
+    fn proof(&self, transaction: &Transaction) -> Option<Proof> {
+        const NOTE_LENGTH: usize = 100;
+        let task_id = match transaction.source {
+            Source::Task(task_id) => task_id,
+            _ => return None,
+        };
+        let proof = self.proofs.get(&(task_id, transaction.timestamp))?;
+        let note = proof
+            .note
+            .as_deref()
+            .map(|note| match note.char_indices().nth(NOTE_LENGTH) {
+                Some((end, _)) => format!("{}…", &note[..end]),
+                None => note.to_owned(),
+            });
+        Some(Proof {
+            note,
+            attachment: proof.attachment.clone(),
+        })
     }
 
     fn get_embed(&self) -> CreateEmbed {
@@ -65,23 +125,30 @@ impl Page {
                 (String::new(), String::new(), String::new()),
                 |a, b| {
                     (
-                        a.0 + &b.0 + "\n",
-                        a.1 + &b.1.to_string() + "\n",
-                        a.2 + &DateTime::<Utc>::from_utc(
-                            NaiveDateTime::from_timestamp(b.2.unwrap(), 0),
-                            Utc,
-                        )
-                        .format("%m/%d/%Y (%I:%M %p)\n")
-                        .to_string(),
+                        a.0 + &format!("`#{}` {}\n", b.id, b.reason),
+                        a.1 + &b.amount.to_string() + "\n",
+                        a.2 + &self.settings.format(b.timestamp, "%m/%d/%Y (%I:%M %p)\n"),
                     )
                 },
             );
+            let proofs = records
+                .iter()
+                .filter_map(|transaction| {
+                    let proof = self.proof(transaction)?;
+                    Some(format!("`#{}` 📎 {}\n", transaction.id, proof))
+                })
+                .collect::<String>();
 
             embed
-                .title("Transactions history :>")
+                .title(match &self.tag {
+                    Some(tag) => format!("Transactions tagged `{}` :>", tag),
+                    None => "Transactions history :>".to_owned(),
+                })
                 .field("Transaction Name", task, true)
                 .field("Coins", points, true)
-                .field("Date", completed, true);
+                .field("Date", completed, true)
+                .field("By category", &self.breakdown, false)
+                .description(proofs);
         } else {
             embed
                 .title("It's a little empty here?")
@@ -0,0 +1,219 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use serenity::{
+    builder::{CreateApplicationCommand, CreateInteractionResponse},
+    client::bridge::gateway::ShardMessenger,
+    futures::{lock::Mutex, StreamExt},
+    http::Http,
+    model::interactions::{
+        application_command::ApplicationCommandInteraction, InteractionResponseType,
+    },
+};
+
+use crate::util::{
+    db::RecordType,
+    events::Action,
+    trash::{Trashed, RETENTION_DAYS},
+    Origin, Surface, UserStore,
+};
+
+/// How many changes to list at once, the select menu fits 25 at most
+const SHOWN_BATCHES: usize = 10;
+
+/// A short description of a trashed record, e.g. "`#12` Laundry (daily)"
+pub fn describe(entry: &Trashed) -> String {
+    format!(
+        "`#{}` {} ({})",
+        entry.record.id(),
+        entry.record.name(),
+        match entry.record.record_type() {
+            RecordType::Daily => "daily",
+            RecordType::Pending => "pending",
+            RecordType::Transaction => "transaction",
+        }
+    )
+}
+
+fn ago(timestamp: i64) -> String {
+    let elapsed = Utc::now().timestamp() - timestamp;
+    if elapsed >= 24 * 3600 {
+        format!(
+            "{}d {}h ago",
+            elapsed / (24 * 3600),
+            elapsed % (24 * 3600) / 3600
+        )
+    } else {
+        format!("{}h {}m ago", elapsed / 3600, elapsed % 3600 / 60)
+    }
+}
+
+/// Browse the trash and restore what was deleted or overwritten
+pub struct Trash {
+    store: Arc<dyn UserStore>,
+    user_id: u64,
+    trashed: Vec<Trashed>,
+}
+
+impl Trash {
+    pub fn new(store: &Arc<dyn UserStore>, user_id: u64) -> Self {
+        Self {
+            store: store.clone(),
+            user_id,
+            trashed: store.trash(user_id).expect("Cannot fetch the trash"),
+        }
+    }
+
+    pub fn create_application_command() -> CreateApplicationCommand {
+        let mut command = CreateApplicationCommand::default();
+        command
+            .name("trash")
+            .description("Bring back records you deleted or edited :>");
+        command
+    }
+
+    fn restore(&mut self, batch: i64) {
+        self.store
+            .restore(
+                Origin::user(self.user_id, Surface::Trash),
+                self.user_id,
+                batch,
+            )
+            .expect("Cannot restore from the trash");
+        self.trashed = self
+            .store
+            .trash(self.user_id)
+            .expect("Cannot fetch the trash");
+    }
+
+    /// The trashed records of the newest changes, grouped by the change that trashed them
+    fn batches(&self) -> Vec<Vec<&Trashed>> {
+        let mut batches: Vec<Vec<&Trashed>> = Vec::new();
+        for entry in &self.trashed {
+            match batches.last_mut() {
+                Some(batch) if batch[0].batch == entry.batch => batch.push(entry),
+                _ => {
+                    if batches.len() == SHOWN_BATCHES {
+                        break;
+                    }
+                    batches.push(vec![entry])
+                }
+            }
+        }
+        batches
+    }
+
+    fn delegate_interaction_response<'a>(
+        &self,
+        interaction: &'a mut CreateInteractionResponse,
+    ) -> &'a mut CreateInteractionResponse {
+        let batches = self.batches();
+        if batches.is_empty() {
+            return interaction.interaction_response_data(|data| {
+                data.create_embed(|embed| {
+                    embed
+                        .title("Your trash is empty!")
+                        .description("Nothing has been deleted or edited lately :D")
+                        .footer(|footer| {
+                            footer.text(format!("Records are kept for {} days", RETENTION_DAYS))
+                        })
+                })
+                .components(|components| components)
+            });
+        }
+
+        let (records, changes, when) = batches.iter().flatten().fold(
+            (String::new(), String::new(), String::new()),
+            |e, entry| {
+                (
+                    e.0 + &describe(entry) + "\n",
+                    e.1 + match entry.action {
+                        Action::Update => "✏️ Edited\n",
+                        _ => "🗑️ Deleted\n",
+                    },
+                    e.2 + &ago(entry.trashed) + "\n",
+                )
+            },
+        );
+
+        interaction.interaction_response_data(|data| {
+            data.create_embed(|embed| {
+                embed
+                    .title("Your trash :>")
+                    .field("Record", records, true)
+                    .field("Change", changes, true)
+                    .field("When", when, true)
+                    .footer(|footer| {
+                        footer.text(format!("Records are kept for {} days", RETENTION_DAYS))
+                    })
+            })
+            .components(|components| {
+                components.create_action_row(|row| {
+                    row.create_select_menu(|menu| {
+                        menu.options(|options| {
+                            for batch in &batches {
+                                options.create_option(|option| {
+                                    let label = match batch.len() {
+                                        1 => batch[0].record.name().to_owned(),
+                                        n => {
+                                            format!("{} and {} more", batch[0].record.name(), n - 1)
+                                        }
+                                    };
+                                    option
+                                        .label(label)
+                                        .description(ago(batch[0].trashed))
+                                        .value(batch[0].batch)
+                                });
+                            }
+                            options
+                        })
+                        .placeholder("Bring something back :>")
+                        .custom_id("restore_trash_menu")
+                    })
+                })
+            });
+            data
+        })
+    }
+
+    pub async fn handle_interaction(
+        &mut self,
+        http: &Arc<Http>,
+        interaction: ApplicationCommandInteraction,
+        shard_messenger: &ShardMessenger,
+    ) -> Result<(), serenity::Error> {
+        interaction
+            .create_interaction_response(http, |interaction| {
+                self.delegate_interaction_response(interaction)
+            })
+            .await?;
+
+        let collector = interaction
+            .get_interaction_response(http)
+            .await?
+            .await_component_interactions(shard_messenger)
+            .timeout(Duration::from_secs(15))
+            .author_id(self.user_id)
+            .await;
+
+        let trash = &Arc::new(Mutex::new(self));
+        collector
+            .for_each(|interaction| async move {
+                let mut trash = trash.lock().await;
+                if let Ok(batch) = interaction.data.values[0].parse() {
+                    trash.restore(batch);
+                }
+                interaction
+                    .create_interaction_response(http, |interaction| {
+                        trash
+                            .delegate_interaction_response(interaction)
+                            .kind(InteractionResponseType::UpdateMessage)
+                    })
+                    .await
+                    .expect("Unable to update interaction");
+            })
+            .await;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use serenity::{
+    builder::CreateApplicationCommand, http::Http,
+    model::interactions::application_command::ApplicationCommandInteraction,
+};
+
+use super::trash::describe;
+use crate::util::{Origin, Surface, UserStore};
+
+/// Bring back whatever was deleted or overwritten last
+pub struct Undo<'a> {
+    store: &'a dyn UserStore,
+    user_id: u64,
+}
+
+impl<'a> Undo<'a> {
+    pub fn new(store: &'a dyn UserStore, user_id: u64) -> Self {
+        Self { store, user_id }
+    }
+
+    pub fn create_application_command() -> CreateApplicationCommand {
+        let mut command = CreateApplicationCommand::default();
+        command
+            .name("undo")
+            .description("Bring back what you last deleted or edited!");
+        command
+    }
+
+    pub async fn handle_interaction(
+        &mut self,
+        http: &Arc<Http>,
+        interaction: ApplicationCommandInteraction,
+    ) -> Result<(), serenity::Error> {
+        let restored = self
+            .store
+            .undo(Origin::user(self.user_id, Surface::Undo), self.user_id)
+            .expect("Cannot undo for user");
+
+        let response = if restored.is_empty() {
+            "There is nothing to undo :<".to_owned()
+        } else {
+            format!(
+                "Brought back {}!",
+                restored.iter().map(describe).collect::<Vec<_>>().join(", ")
+            )
+        };
+
+        interaction
+            .create_interaction_response(http, |reply| {
+                reply.interaction_response_data(|data| data.content(response))
+            })
+            .await?;
+
+        Ok(())
+    }
+}
@@ -1,9 +1,331 @@
-use chrono::{DateTime, Duration, Utc};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
 
-pub fn get_tomorrow() -> DateTime<Utc> {
-    (Utc::now() + Duration::days(1)).date().and_hms(0, 0, 0)
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serenity::{
+    builder::CreateApplicationCommandOption,
+    client::bridge::gateway::ShardMessenger,
+    http::Http,
+    model::{
+        channel::{Attachment, Message},
+        interactions::{
+            application_command::{
+                ApplicationCommandInteraction,
+                ApplicationCommandInteractionDataOptionValue as ApplicationValue,
+                ApplicationCommandOptionType,
+            },
+            message_component::{
+                ActionRowComponent, ButtonStyle, InputTextStyle, MessageComponentInteraction,
+            },
+            InteractionResponseType,
+        },
+    },
+};
+
+use crate::util::{
+    records::parse_tags, Origin, Proof, RecordId, Settings, Surface, TaskKind, Transaction,
+    UserStore,
+};
+
+/// How long a user has for each step of giving proof once they completed a task
+const PROOF_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// When the user's next day begins
+pub fn get_tomorrow(settings: &Settings) -> DateTime<Utc> {
+    settings.start_of(settings.today().succ())
+}
+
+/// The day it is for the user
+pub fn get_today(settings: &Settings) -> NaiveDate {
+    settings.today()
+}
+
+/// Parse a date such as `2022-01-31`, or a date and time such as `2022-01-31 18:00`, in the
+/// user's timezone. A date alone means the very end of that day.
+pub fn parse_datetime(input: &str, settings: &Settings) -> Option<i64> {
+    let input = input.trim();
+    NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M")
+        .or_else(|_| {
+            NaiveDate::parse_from_str(input, "%Y-%m-%d").map(|date| date.and_hms(23, 59, 59))
+        })
+        .ok()
+        .map(|datetime| settings.timestamp(datetime))
+}
+
+/// Split a checklist such as `vacuum; dishes; laundry` into its items, in order
+pub fn parse_checklist(input: &str) -> Vec<String> {
+    input
+        .split(';')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// A bar such as `▰▰▰▱▱▱▱▱ 3/8` showing how far along a task with a target is
+pub fn progress_bar(progress: u32, target: u32) -> String {
+    const CELLS: u32 = 8;
+    let filled = (progress * CELLS / target.max(1)).min(CELLS);
+    format!(
+        "{}{} {}/{}",
+        "▰".repeat(filled as usize),
+        "▱".repeat((CELLS - filled) as usize),
+        progress,
+        target
+    )
+}
+
+/// The increments offered for a task with a target: one more, a quarter of the target, and
+/// whatever is left
+pub fn progress_steps(progress: u32, target: u32) -> Vec<u32> {
+    let left = target.saturating_sub(progress);
+    let mut steps = vec![1, target / 4, left];
+    steps.retain(|step| *step > 0 && *step <= left);
+    steps.sort_unstable();
+    steps.dedup();
+    steps
+}
+
+/// The `tag` option of commands that can be narrowed down to a single tag
+pub fn tag_option(
+    option: &mut CreateApplicationCommandOption,
+) -> &mut CreateApplicationCommandOption {
+    option
+        .name("tag")
+        .description("Only show what is tagged with this, e.g. health")
+        .kind(ApplicationCommandOptionType::String)
+}
+
+/// The tag a command was narrowed down to, if any
+pub fn tag_filter(command: &ApplicationCommandInteraction) -> Option<String> {
+    command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == "tag")
+        .and_then(|option| match &option.resolved {
+            Some(ApplicationValue::String(tag)) => parse_tags(tag).into_iter().next(),
+            _ => None,
+        })
+}
+
+/// Coins gained or spent per tag, one tag per line. A transaction with several tags counts
+/// towards each of them.
+pub fn breakdown(transactions: &[Transaction]) -> String {
+    let mut totals = BTreeMap::<&str, i64>::new();
+    for transaction in transactions {
+        if transaction.tags.is_empty() {
+            *totals.entry("untagged").or_default() += transaction.amount;
+        }
+        for tag in &transaction.tags {
+            *totals.entry(tag).or_default() += transaction.amount;
+        }
+    }
+    if totals.is_empty() {
+        return "Nothing yet :<".to_owned();
+    }
+    totals
+        .into_iter()
+        .map(|(tag, total)| format!("**{}**: :coin:x{}\n", tag, total))
+        .collect()
+}
+
+/// Offer to keep proof of a task that was just completed from `interaction`. The user picks
+/// whether to write a note or send an image, and the prompt goes away if they don't. Each
+/// completion keeps at most one proof.
+pub async fn ask_for_proof(
+    http: Arc<Http>,
+    shard: ShardMessenger,
+    interaction: Arc<MessageComponentInteraction>,
+    store: Arc<dyn UserStore>,
+    surface: Surface,
+    kind: TaskKind,
+    id: RecordId,
+) -> Result<(), serenity::Error> {
+    let user_id = interaction.user.id.0;
+    let (title, completed) = match store
+        .tasks(user_id, kind)
+        .expect("Cannot fetch tasks for user")
+        .into_iter()
+        .find(|task| task.id == id)
+        .and_then(|task| Some((task.title, task.completed_at?)))
+    {
+        Some(task) => task,
+        None => return Ok(()),
+    };
+
+    let prompt = interaction
+        .create_followup_message(&http, |message| {
+            message
+                .content(format!(
+                    "Done with **{}**! Want to keep proof of it?",
+                    title
+                ))
+                .ephemeral(true)
+                .components(|components| {
+                    components.create_action_row(|row| {
+                        row.create_button(|button| {
+                            button
+                                .label("Write a note")
+                                .custom_id("proof_note")
+                                .style(ButtonStyle::Primary)
+                        })
+                        .create_button(|button| {
+                            button
+                                .label("Send an image")
+                                .custom_id("proof_image")
+                                .style(ButtonStyle::Primary)
+                        })
+                    })
+                })
+        })
+        .await?;
+
+    let choice = prompt
+        .await_component_interaction(&shard)
+        .author_id(user_id)
+        .timeout(PROOF_TIMEOUT)
+        .await;
+    let proof = match choice {
+        Some(choice) if choice.data.custom_id == "proof_note" => {
+            note_proof(&http, &shard, &choice, &prompt).await?
+        }
+        Some(choice) => image_proof(&http, &shard, &choice).await?,
+        None => None,
+    };
+    let proof = match proof {
+        Some(proof) => proof,
+        None => return interaction.delete_followup_message(&http, prompt.id).await,
+    };
+
+    let attached = store
+        .attach_proof(
+            Origin::user(user_id, surface),
+            user_id,
+            kind,
+            id,
+            completed,
+            proof,
+        )
+        .expect("Cannot attach proof for user");
+    let response = if attached {
+        format!("Kept your proof for **{}** :D", title)
+    } else {
+        format!(
+            "**{}** was changed or already has proof, so I didn't keep this one :<",
+            title
+        )
+    };
+    interaction
+        .edit_followup_message(&http, prompt.id, |message| {
+            message
+                .content(response)
+                .components(|components| components)
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Ask for a note in a modal opened by `choice`, which was made on `prompt`
+async fn note_proof(
+    http: &Arc<Http>,
+    shard: &ShardMessenger,
+    choice: &MessageComponentInteraction,
+    prompt: &Message,
+) -> Result<Option<Proof>, serenity::Error> {
+    choice
+        .create_interaction_response(http, |response| {
+            response
+                .kind(InteractionResponseType::Modal)
+                .interaction_response_data(|modal| {
+                    modal
+                        .custom_id("proof_note_modal")
+                        .title("Keep a note as proof")
+                        .components(|components| {
+                            components.create_action_row(|row| {
+                                row.create_input_text(|input| {
+                                    input
+                                        .custom_id("note")
+                                        .label("What did you do?")
+                                        .style(InputTextStyle::Paragraph)
+                                        .max_length(1000)
+                                })
+                            })
+                        })
+                })
+        })
+        .await?;
+
+    let submitted = match prompt
+        .await_modal_interaction(shard)
+        .author_id(choice.user.id)
+        .timeout(PROOF_TIMEOUT)
+        .await
+    {
+        Some(submitted) => submitted,
+        None => return Ok(None),
+    };
+    submitted
+        .create_interaction_response(http, |response| {
+            response.kind(InteractionResponseType::DeferredUpdateMessage)
+        })
+        .await?;
+
+    let note = submitted
+        .data
+        .components
+        .iter()
+        .flat_map(|row| &row.components)
+        .find_map(|component| match component {
+            ActionRowComponent::InputText(input) => Some(input.value.clone()),
+            _ => None,
+        });
+    Ok(Proof::new(note, None))
+}
+
+/// The first image attached to a message, going by its type or, when Discord didn't say, by
+/// it having a size
+fn first_image(message: &Message) -> Option<&Attachment> {
+    message.attachments.iter().find(|attachment| {
+        attachment
+            .content_type
+            .as_deref()
+            .map_or(attachment.width.is_some(), |kind| {
+                kind.starts_with("image/")
+            })
+    })
 }
 
-pub fn get_today() -> DateTime<Utc> {
-    Utc::now().date().and_hms(0, 0, 0)
+/// Wait for the user who made `choice` to send an image in its channel. Anything they say
+/// without one, or with other kinds of files, is left alone.
+async fn image_proof(
+    http: &Arc<Http>,
+    shard: &ShardMessenger,
+    choice: &MessageComponentInteraction,
+) -> Result<Option<Proof>, serenity::Error> {
+    choice
+        .create_interaction_response(http, |response| {
+            response
+                .kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|message| {
+                    message
+                        .content("Send the image here within a minute, with a note if you like :>")
+                        .components(|components| components)
+                })
+        })
+        .await?;
+
+    let reply = choice
+        .channel_id
+        .await_reply(shard)
+        .author_id(choice.user.id)
+        .filter(|message| first_image(message).is_some())
+        .timeout(PROOF_TIMEOUT)
+        .await;
+    Ok(reply.and_then(|reply| {
+        Proof::new(
+            Some(reply.content.clone()),
+            first_image(&reply).map(|image| image.url.clone()),
+        )
+    }))
 }
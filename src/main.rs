@@ -1,4 +1,7 @@
-use std::env;
+use std::{
+    env,
+    sync::{Arc, RwLock},
+};
 
 use serenity::{
     async_trait,
@@ -11,33 +14,85 @@ pub mod commands;
 pub mod util;
 
 use crate::commands::modify::Modify;
-pub use crate::util::Records;
-use commands::{info::Info, Daily, Pending, Transactions};
-struct Handler;
+use crate::util::{
+    backup,
+    catalog::{self, Catalog, CatalogConfig},
+    db::Database,
+    penalty::{self, PenaltyConfig},
+    streaks::StreakBonuses,
+    BlockingStore, UserStore,
+};
+use commands::{
+    info::Info,
+    util::{tag_filter, tag_option},
+    Daily, History, Inventory, Pending, Preferences, Shop, Transactions, Trash, Undo,
+};
+struct Handler {
+    store: Arc<dyn UserStore>,
+    penalties: PenaltyConfig,
+    bonuses: StreakBonuses,
+    catalog: Arc<RwLock<Catalog>>,
+}
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
         if let Interaction::ApplicationCommand(command) = interaction {
             match command.data.name.as_str() {
-                "daily" => Daily::new("resources/users.db", command.user.id.0)
+                "daily" => Daily::new(
+                    &self.store,
+                    command.user.id.0,
+                    &self.bonuses,
+                    tag_filter(&command),
+                )
+                .handle_interaction(&ctx.http, command, &ctx.shard)
+                .await
+                .expect("Something went wrong with daily command!"),
+                "pending" => Pending::new(
+                    &self.store,
+                    command.user.id.0,
+                    &self.penalties,
+                    tag_filter(&command),
+                )
+                .handle_interaction(&ctx.http, command, &ctx.shard)
+                .await
+                .expect("Something went wrong with pending command!"),
+                "transactions" => {
+                    Transactions::new(&self.store, command.user.id.0, tag_filter(&command))
+                        .await
+                        .handle_interaction(&ctx.http, command, &ctx.shard)
+                        .await
+                        .expect("Something went wrong with the transactions command!")
+                }
+                "shop" => Shop::new(&self.store, &self.catalog, command.user.id.0)
                     .handle_interaction(&ctx.http, command, &ctx.shard)
                     .await
-                    .expect("Something went wrong with daily command!"),
-                "pending" => Pending::new("resources/users.db", command.user.id.0)
+                    .expect("Something went wrong with the shop command!"),
+                "inventory" => Inventory::new(&self.store, command.user.id.0)
                     .handle_interaction(&ctx.http, command, &ctx.shard)
                     .await
-                    .expect("Something went wrong with pending command!"),
-                "transactions" => Transactions::new("resources/users.db", command.user.id.0)
+                    .expect("Something went wrong with the inventory command!"),
+                "modify" => Modify::new(self.store.as_ref(), command.user.id.0)
+                    .handle_interaction(&ctx.http, command)
                     .await
-                    .handle_interaction(&ctx.http, command, &ctx.shard)
+                    .expect("Something went wrong with the modify command!"),
+                "undo" => Undo::new(self.store.as_ref(), command.user.id.0)
+                    .handle_interaction(&ctx.http, command)
                     .await
-                    .expect("Something went wrong with the transactions command!"),
-                "modify" => Modify::new("resources/users.db", command.user.id.0)
+                    .expect("Something went wrong with the undo command!"),
+                "settings" => Preferences::new(self.store.as_ref(), command.user.id.0)
                     .handle_interaction(&ctx.http, command)
                     .await
-                    .expect("Something went wrong with the modify command!"),
-                "aboutme" => Info::new("resources/users.db", command.user.id.0)
+                    .expect("Something went wrong with the settings command!"),
+                "history" => History::new(self.store.as_ref(), command.user.id.0)
+                    .handle_interaction(&ctx.http, command)
+                    .await
+                    .expect("Something went wrong with the history command!"),
+                "trash" => Trash::new(&self.store, command.user.id.0)
+                    .handle_interaction(&ctx.http, command, &ctx.shard)
+                    .await
+                    .expect("Something went wrong with the trash command!"),
+                "aboutme" => Info::new(&self.store, command.user.id.0)
                     .handle_interaction(&ctx.http, command, &ctx.shard)
                     .await
                     .expect("Something went wrong with the info command!"),
@@ -71,16 +126,19 @@ impl EventHandler for Handler {
                         command
                             .name("daily")
                             .description("Fetch your daily tasks :D")
+                            .create_option(tag_option)
                     })
                     .create_application_command(|command| {
                         command
                             .name("pending")
                             .description("Fetch your incomplete tasks! \\o/")
+                            .create_option(tag_option)
                     })
                     .create_application_command(|command| {
                         command
                             .name("transactions")
                             .description("Fetch your transactions history :>")
+                            .create_option(tag_option)
                     })
                     .create_application_command(|command| {
                         command
@@ -89,6 +147,12 @@ impl EventHandler for Handler {
                     })
                     .create_application_command(|command| command.name("nya").description("nya :D"))
                     .add_application_command(Modify::create_application_command())
+                    .add_application_command(Undo::create_application_command())
+                    .add_application_command(Trash::create_application_command())
+                    .add_application_command(Preferences::create_application_command())
+                    .add_application_command(Shop::create_application_command())
+                    .add_application_command(Inventory::create_application_command())
+                    .add_application_command(History::create_application_command())
             },
         )
         .await
@@ -103,6 +167,25 @@ impl EventHandler for Handler {
 
 #[tokio::main]
 async fn main() {
+    let db = Database::open("resources/users.db").expect("Unable to open the database");
+    let (from, to) = db.migrate().expect("Unable to migrate the database");
+    if from != to {
+        println!(
+            "Migrated the database from schema version {} to {}",
+            from, to
+        );
+    }
+
+    tokio::spawn(backup::schedule(
+        db.clone(),
+        backup::BackupConfig::from_env(),
+    ));
+    let penalties = PenaltyConfig::from_env();
+    tokio::spawn(penalty::schedule(Arc::new(db.clone()), penalties));
+    let store: Arc<dyn UserStore> = Arc::new(BlockingStore(db));
+    let catalog = Arc::new(RwLock::new(Catalog::default()));
+    tokio::spawn(catalog::watch(catalog.clone(), CatalogConfig::from_env()));
+
     let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
 
     let application_id: u64 = env::var("APPLICATION_ID")
@@ -111,7 +194,12 @@ async fn main() {
         .expect("Application id is not a valid id");
 
     let mut client = Client::builder(token)
-        .event_handler(Handler)
+        .event_handler(Handler {
+            store,
+            penalties,
+            bonuses: StreakBonuses::from_env(),
+            catalog,
+        })
         .application_id(application_id)
         .await
         .expect("Error creating client");
@@ -0,0 +1,146 @@
+//! Scheduled snapshots of the database, taken with SQLite's online backup API so they are
+//! consistent even while the bot is writing.
+use std::{
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::Utc;
+
+use super::db::Database;
+
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const SNAPSHOT_EXTENSION: &str = "db";
+
+#[derive(Debug)]
+pub enum BackupError {
+    Io(io::Error),
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackupError::Io(e) => write!(f, "backup failed: {}", e),
+            BackupError::Sqlite(e) => write!(f, "backup failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+impl From<io::Error> for BackupError {
+    fn from(e: io::Error) -> Self {
+        BackupError::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for BackupError {
+    fn from(e: rusqlite::Error) -> Self {
+        BackupError::Sqlite(e)
+    }
+}
+
+/// Where, how often and how many snapshots to keep
+#[derive(Clone, Debug)]
+pub struct BackupConfig {
+    pub dir: PathBuf,
+    /// Time between snapshots, `None` to only take them by hand
+    pub interval: Option<Duration>,
+    /// How many snapshots to keep, older ones are deleted
+    pub keep: usize,
+}
+
+impl BackupConfig {
+    /// Read the configuration from `BACKUP_DIR`, `BACKUP_INTERVAL_HOURS` and `BACKUP_KEEP`,
+    /// defaulting to a daily snapshot in `resources/backups` kept for a week. An interval of
+    /// 0 hours turns scheduled snapshots off.
+    pub fn from_env() -> Self {
+        let hours: u64 = env::var("BACKUP_INTERVAL_HOURS").map_or(24, |hours| {
+            hours
+                .parse()
+                .expect("BACKUP_INTERVAL_HOURS must be an integer")
+        });
+        Self {
+            dir: env::var("BACKUP_DIR")
+                .unwrap_or_else(|_| "resources/backups".to_owned())
+                .into(),
+            interval: (hours > 0).then(|| Duration::from_secs(hours * 3600)),
+            keep: env::var("BACKUP_KEEP").map_or(7, |keep| {
+                keep.parse().expect("BACKUP_KEEP must be an integer")
+            }),
+        }
+    }
+}
+
+/// Every snapshot in `dir`, oldest first
+pub fn snapshots(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut snapshots = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == SNAPSHOT_EXTENSION)
+                && path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with(SNAPSHOT_PREFIX))
+        })
+        .collect::<Vec<_>>();
+    // The names embed the time they were taken at, so they sort chronologically
+    snapshots.sort();
+
+    Ok(snapshots)
+}
+
+/// Take a snapshot of the database into `dir`, then delete all but the `keep` newest ones.
+/// Returns the path of the new snapshot.
+pub fn snapshot(db: &Database, dir: &Path, keep: usize) -> Result<PathBuf, BackupError> {
+    fs::create_dir_all(dir)?;
+
+    let path = dir.join(format!(
+        "{}{}.{}",
+        SNAPSHOT_PREFIX,
+        Utc::now().format("%Y%m%d-%H%M%S"),
+        SNAPSHOT_EXTENSION
+    ));
+    db.backup_to(&path)?;
+
+    // Never delete the snapshot that was just taken
+    let snapshots = snapshots(dir)?;
+    for old in snapshots
+        .iter()
+        .take(snapshots.len().saturating_sub(keep.max(1)))
+    {
+        fs::remove_file(old)?;
+    }
+
+    Ok(path)
+}
+
+/// Take a snapshot every `config.interval`, forever, unless scheduled snapshots are off
+pub async fn schedule(db: Database, config: BackupConfig) {
+    let mut interval = match config.interval {
+        Some(interval) => tokio::time::interval(interval),
+        None => {
+            println!("Scheduled backups are off");
+            return;
+        }
+    };
+    loop {
+        interval.tick().await;
+
+        let (db, config) = (db.clone(), config.clone());
+        match tokio::task::spawn_blocking(move || snapshot(&db, &config.dir, config.keep)).await {
+            Ok(Ok(path)) => println!("Backed up the database to {}", path.display()),
+            Ok(Err(e)) => println!("{}", e),
+            Err(e) => println!("Backup task failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,660 @@
+//! The reward shop's catalog: the items for sale, read from a TOML, JSON or CSV file and
+//! reloaded whenever the file changes, and what came of buying them.
+use std::{
+    collections::HashSet,
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use super::{records::Transaction, settings::Settings};
+
+/// Discord cuts select menu labels off at this many characters
+const MAX_NAME_LENGTH: usize = 100;
+
+#[derive(Debug)]
+pub enum CatalogError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    Csv(csv::Error),
+    /// The file is not a `.toml`, `.json` or `.csv` file
+    Format(PathBuf),
+    /// An item that cannot be sold, by its position in the file counting from 1
+    Invalid {
+        item: usize,
+        reason: String,
+    },
+    /// Two items share a name, ignoring case and surrounding spaces
+    Duplicate(String),
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogError::Io(e) => write!(f, "cannot read the catalog: {}", e),
+            CatalogError::Toml(e) => write!(f, "invalid TOML: {}", e),
+            CatalogError::Json(e) => write!(f, "invalid JSON: {}", e),
+            CatalogError::Csv(e) => write!(f, "invalid CSV: {}", e),
+            CatalogError::Format(path) => {
+                write!(f, "{} is not a .toml, .json or .csv file", path.display())
+            }
+            CatalogError::Invalid { item, reason } => write!(f, "item {}: {}", item, reason),
+            CatalogError::Duplicate(name) => write!(f, "more than one item is named {}", name),
+        }
+    }
+}
+
+impl std::error::Error for CatalogError {}
+
+impl From<io::Error> for CatalogError {
+    fn from(e: io::Error) -> Self {
+        CatalogError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for CatalogError {
+    fn from(e: toml::de::Error) -> Self {
+        CatalogError::Toml(e)
+    }
+}
+
+impl From<serde_json::Error> for CatalogError {
+    fn from(e: serde_json::Error) -> Self {
+        CatalogError::Json(e)
+    }
+}
+
+impl From<csv::Error> for CatalogError {
+    fn from(e: csv::Error) -> Self {
+        CatalogError::Csv(e)
+    }
+}
+
+/// How often an item's stock is refilled back to its `qty`. Written `daily`, `weekly`,
+/// `monthly` or `every 3 days`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Restock {
+    Daily,
+    /// Every Monday
+    Weekly,
+    /// On the first of every month
+    Monthly,
+    /// Once every this many days, counting from 1970-01-01
+    EveryDays(u32),
+}
+
+impl Restock {
+    pub fn parse(restock: &str) -> Option<Self> {
+        let restock = restock.trim().to_lowercase();
+        match restock.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["daily"] => Some(Restock::Daily),
+            ["weekly"] => Some(Restock::Weekly),
+            ["monthly"] => Some(Restock::Monthly),
+            ["every", days, "days"] => days
+                .parse()
+                .ok()
+                .filter(|days| *days > 0)
+                .map(Restock::EveryDays),
+            _ => None,
+        }
+    }
+
+    /// The day the stock was last refilled, as of `today`
+    pub fn period_start(&self, today: NaiveDate) -> NaiveDate {
+        match self {
+            Restock::Daily => today,
+            Restock::Weekly => {
+                today - chrono::Duration::days(today.weekday().num_days_from_monday() as i64)
+            }
+            Restock::Monthly => NaiveDate::from_ymd(today.year(), today.month(), 1),
+            Restock::EveryDays(days) => {
+                let elapsed = (today - NaiveDate::from_ymd(1970, 1, 1)).num_days();
+                today - chrono::Duration::days(elapsed.rem_euclid(*days as i64))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Restock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Restock::Daily => write!(f, "daily"),
+            Restock::Weekly => write!(f, "weekly"),
+            Restock::Monthly => write!(f, "monthly"),
+            Restock::EveryDays(days) => write!(f, "every {} days", days),
+        }
+    }
+}
+
+impl From<Restock> for String {
+    fn from(restock: Restock) -> Self {
+        restock.to_string()
+    }
+}
+
+impl FromStr for Restock {
+    type Err = String;
+
+    fn from_str(restock: &str) -> Result<Self, Self::Err> {
+        Restock::parse(restock).ok_or_else(|| format!("Invalid restock {}", restock))
+    }
+}
+
+impl TryFrom<String> for Restock {
+    type Error = String;
+
+    fn try_from(restock: String) -> Result<Self, Self::Error> {
+        restock.parse()
+    }
+}
+
+/// Something the shop sells
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Item {
+    pub name: String,
+    /// Coins it costs
+    pub cost: i64,
+    /// How many there are for sale, `None` for no limit. Every purchase takes one, so the
+    /// stock of a catalog item goes down whoever buys it.
+    #[serde(default)]
+    pub qty: Option<i64>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// How often the stock goes back up to `qty`, `None` to never restock
+    #[serde(default)]
+    pub restock: Option<Restock>,
+    /// The first day the item is for sale, in each user's timezone
+    #[serde(default)]
+    pub available_from: Option<NaiveDate>,
+    /// The last day the item is for sale, in each user's timezone
+    #[serde(default)]
+    pub available_until: Option<NaiveDate>,
+    /// The user who added the item to their own shop, `None` for catalog items
+    #[serde(skip)]
+    pub owner: Option<u64>,
+}
+
+impl Item {
+    /// Whether the item is for sale on `today`
+    pub fn is_available(&self, today: NaiveDate) -> bool {
+        self.available_from.is_none_or(|from| from <= today)
+            && self.available_until.is_none_or(|until| today <= until)
+    }
+
+    /// When the stock was last refilled, 0 if it never is. A user's own items restock with
+    /// their days, in `settings`, while the catalog's stock is shared and restocks with UTC days.
+    pub fn restocked(&self, settings: &Settings) -> i64 {
+        let settings = match self.owner {
+            Some(_) => *settings,
+            None => Settings::default(),
+        };
+        self.restock.map_or(0, |restock| {
+            settings
+                .start_of(restock.period_start(settings.today()))
+                .timestamp()
+        })
+    }
+
+    /// How many are left after selling `sold` since the last restock, `None` if there is no
+    /// limit
+    pub fn stock(&self, sold: i64) -> Option<i64> {
+        Some((self.qty? - sold).max(0))
+    }
+
+    /// Why the item cannot be sold, if it cannot
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("the name is empty".to_owned());
+        }
+        if self.name.chars().count() > MAX_NAME_LENGTH {
+            return Err(format!(
+                "{} is longer than {} characters",
+                self.name, MAX_NAME_LENGTH
+            ));
+        }
+        if self.cost <= 0 {
+            return Err(format!(
+                "{} costs {}, it has to cost at least 1",
+                self.name, self.cost
+            ));
+        }
+        match self.qty {
+            Some(qty) if qty < 0 => Err(format!(
+                "{} has a quantity of {}, leave it out for no limit",
+                self.name, qty
+            )),
+            _ => Ok(()),
+        }?;
+        if self.restock.is_some() && self.qty.is_none() {
+            return Err(format!(
+                "{} restocks but has no qty to restock to",
+                self.name
+            ));
+        }
+        match (self.available_from, self.available_until) {
+            (Some(from), Some(until)) if until < from => Err(format!(
+                "{} is available until {}, before it is available from {}",
+                self.name, until, from
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What an item's name is matched by: trimmed and lowercased. Catalogs, shops and stock all
+/// compare names this way, whichever store holds them.
+pub fn name_key(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// What came of trying to buy an item
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Purchase {
+    /// Bought, leaving the user with this balance
+    Bought { balance: i64 },
+    /// Not bought, the user only has this balance
+    TooExpensive { balance: i64 },
+    /// Not bought, all there are were sold until the next restock
+    SoldOut,
+    /// Not bought, the item is not for sale today
+    Unavailable,
+}
+
+/// Why the user cannot buy `item` right now, given how many were sold since the last restock,
+/// or `None` if they can
+pub fn refusal(
+    item: &Item,
+    transactions: &[Transaction],
+    sold: i64,
+    settings: &Settings,
+) -> Option<Purchase> {
+    if !item.is_available(settings.today()) {
+        return Some(Purchase::Unavailable);
+    }
+    if item.stock(sold) == Some(0) {
+        return Some(Purchase::SoldOut);
+    }
+    let balance = transactions
+        .iter()
+        .map(|transaction| transaction.amount)
+        .sum();
+    (balance < item.cost).then_some(Purchase::TooExpensive { balance })
+}
+
+/// The TOML and JSON catalogs are a list of `items`
+#[derive(Deserialize)]
+struct Items {
+    #[serde(default)]
+    items: Vec<Item>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Catalog {
+    pub items: Vec<Item>,
+}
+
+impl Catalog {
+    /// Read a catalog, in the format its extension names
+    pub fn from_file(path: &Path) -> Result<Self, CatalogError> {
+        let contents = fs::read_to_string(path)?;
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+
+        let items = match extension.as_deref() {
+            Some("toml") => toml::from_str::<Items>(&contents)?.items,
+            Some("json") => serde_json::from_str::<Items>(&contents)?.items,
+            Some("csv") => csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(contents.as_bytes())
+                .deserialize()
+                .collect::<Result<_, _>>()?,
+            _ => return Err(CatalogError::Format(path.to_owned())),
+        };
+        Self::new(items)
+    }
+
+    /// A catalog of these items, as long as every one of them can be sold and no two share a
+    /// name
+    pub fn new(mut items: Vec<Item>) -> Result<Self, CatalogError> {
+        let mut names = HashSet::new();
+        for (index, item) in items.iter_mut().enumerate() {
+            item.name = item.name.trim().to_owned();
+            item.validate().map_err(|reason| CatalogError::Invalid {
+                item: index + 1,
+                reason,
+            })?;
+            if !names.insert(name_key(&item.name)) {
+                return Err(CatalogError::Duplicate(item.name.clone()));
+            }
+        }
+        Ok(Self { items })
+    }
+
+    /// The item with this name, ignoring case
+    pub fn item(&self, name: &str) -> Option<&Item> {
+        let key = name_key(name);
+        self.items.iter().find(|item| name_key(&item.name) == key)
+    }
+
+    /// The shop a user sees: the catalog followed by the items they added for themselves.
+    /// Catalog names are taken, so an item of theirs that a reloaded catalog now also sells
+    /// is left out until they rename it.
+    pub fn merged(&self, personal: &[Item]) -> Vec<Item> {
+        self.items
+            .iter()
+            .chain(personal.iter().filter(|own| self.item(&own.name).is_none()))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Where the catalog is and how often to look for changes to it
+#[derive(Clone, Debug)]
+pub struct CatalogConfig {
+    pub path: PathBuf,
+    pub reload_interval: Duration,
+}
+
+impl CatalogConfig {
+    /// Read the configuration from `SHOP_CATALOG` and `SHOP_RELOAD_SECONDS`, defaulting to
+    /// `resources/shop.toml` checked every 10 seconds
+    pub fn from_env() -> Self {
+        Self {
+            path: env::var("SHOP_CATALOG")
+                .unwrap_or_else(|_| "resources/shop.toml".to_owned())
+                .into(),
+            reload_interval: Duration::from_secs(
+                env::var("SHOP_RELOAD_SECONDS")
+                    .map(|seconds| {
+                        seconds
+                            .parse()
+                            .ok()
+                            .filter(|seconds| *seconds > 0)
+                            .expect("SHOP_RELOAD_SECONDS must be a positive integer")
+                    })
+                    .unwrap_or(10),
+            ),
+        }
+    }
+}
+
+/// Load the catalog into `catalog`, then again every time the file changes, forever. A catalog
+/// that fails to load is reported and the previous one stays up.
+pub async fn watch(catalog: Arc<RwLock<Catalog>>, config: CatalogConfig) {
+    let mut interval = tokio::time::interval(config.reload_interval);
+    let mut loaded: Option<SystemTime> = None;
+    let mut missing = false;
+    loop {
+        interval.tick().await;
+
+        let modified = match fs::metadata(&config.path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(e) => {
+                // Only say so once, and load the file again whenever it comes back
+                if !missing {
+                    println!(
+                        "Cannot find the shop catalog at {}: {}",
+                        config.path.display(),
+                        e
+                    );
+                }
+                missing = true;
+                loaded = None;
+                continue;
+            }
+        };
+        missing = false;
+        if loaded == Some(modified) {
+            continue;
+        }
+        loaded = Some(modified);
+
+        match Catalog::from_file(&config.path) {
+            Ok(reloaded) => {
+                println!(
+                    "Loaded {} shop items from {}",
+                    reloaded.items.len(),
+                    config.path.display()
+                );
+                *catalog.write().expect("Shop catalog is poisoned") = reloaded;
+            }
+            Err(e) => println!(
+                "Cannot load the shop catalog from {}, keeping the previous one: {}",
+                config.path.display(),
+                e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    /// Read a catalog file with these contents, named after the test writing it
+    fn read(name: &str, contents: &str) -> Result<Catalog, CatalogError> {
+        let path = env::temp_dir().join(format!("catalog-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        let catalog = Catalog::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        catalog
+    }
+
+    fn item(name: &str, cost: i64) -> Item {
+        Item {
+            name: name.to_owned(),
+            cost,
+            qty: None,
+            description: None,
+            restock: None,
+            available_from: None,
+            available_until: None,
+            owner: None,
+        }
+    }
+
+    /// The items every valid fixture describes
+    fn expected() -> Vec<Item> {
+        vec![
+            Item {
+                qty: Some(3),
+                description: Some("A slice of cake".to_owned()),
+                restock: Some(Restock::EveryDays(3)),
+                available_until: Some(NaiveDate::from_ymd(2030, 12, 31)),
+                ..item("Cake", 50)
+            },
+            item("Movie night", 200),
+        ]
+    }
+
+    #[test]
+    fn toml_catalogs_are_read() {
+        let catalog = read(
+            "valid.toml",
+            r#"
+                [[items]]
+                name = " Cake "
+                cost = 50
+                qty = 3
+                description = "A slice of cake"
+                restock = "every 3 days"
+                available_until = "2030-12-31"
+
+                [[items]]
+                name = "Movie night"
+                cost = 200
+            "#,
+        )
+        .unwrap();
+        assert_eq!(catalog.items, expected());
+
+        assert!(matches!(
+            read(
+                "invalid.toml",
+                "[[items]]\nname = \"Cake\"\ncost = \"a lot\"\n"
+            ),
+            Err(CatalogError::Toml(_))
+        ));
+    }
+
+    #[test]
+    fn json_catalogs_are_read() {
+        let catalog = read(
+            "valid.json",
+            r#"{"items": [
+                {"name": "Cake", "cost": 50, "qty": 3, "description": "A slice of cake",
+                    "restock": "every 3 days", "available_until": "2030-12-31"},
+                {"name": "Movie night", "cost": 200}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(catalog.items, expected());
+
+        assert!(matches!(
+            read(
+                "invalid.json",
+                r#"{"items": [{"name": "Cake", "cost": 50, "restock": "fortnightly"}]}"#
+            ),
+            Err(CatalogError::Json(_))
+        ));
+    }
+
+    #[test]
+    fn csv_catalogs_are_read() {
+        let catalog = read(
+            "valid.csv",
+            "name, cost, qty, description, restock, available_from, available_until\n\
+            Cake, 50, 3, A slice of cake, every 3 days, , 2030-12-31\n\
+            Movie night, 200, , , , ,\n",
+        )
+        .unwrap();
+        assert_eq!(catalog.items, expected());
+
+        assert!(matches!(
+            read("invalid.csv", "name,cost\nCake,50,3\n"),
+            Err(CatalogError::Csv(_))
+        ));
+    }
+
+    #[test]
+    fn other_formats_are_rejected() {
+        assert!(matches!(
+            read("catalog.yaml", "items: []"),
+            Err(CatalogError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn items_that_cannot_be_sold_are_rejected() {
+        let invalid = [
+            item(" ", 10),
+            item(&"x".repeat(MAX_NAME_LENGTH + 1), 10),
+            item("Free", 0),
+            Item {
+                qty: Some(-1),
+                ..item("Cake", 10)
+            },
+            Item {
+                restock: Some(Restock::Daily),
+                ..item("Cake", 10)
+            },
+            Item {
+                available_from: Some(NaiveDate::from_ymd(2030, 2, 1)),
+                available_until: Some(NaiveDate::from_ymd(2030, 1, 31)),
+                ..item("Cake", 10)
+            },
+        ];
+        for invalid in invalid {
+            assert!(
+                matches!(
+                    Catalog::new(vec![item("Tea", 5), invalid.clone()]),
+                    Err(CatalogError::Invalid { item: 2, .. })
+                ),
+                "{:?}",
+                invalid
+            );
+        }
+        assert!(item(&"x".repeat(MAX_NAME_LENGTH), 1).validate().is_ok());
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        assert!(matches!(
+            Catalog::new(vec![item("Cake", 10), item(" CAKE", 20)]),
+            Err(CatalogError::Duplicate(name)) if name == "CAKE"
+        ));
+        assert!(matches!(
+            Catalog::new(vec![item("Crème", 10), item("CRÈME", 20)]),
+            Err(CatalogError::Duplicate(_))
+        ));
+        assert!(matches!(
+            read(
+                "duplicate.toml",
+                "[[items]]\nname = \"Cake\"\ncost = 1\n[[items]]\nname = \"cake\"\ncost = 2\n"
+            ),
+            Err(CatalogError::Duplicate(_))
+        ));
+    }
+
+    #[test]
+    fn personal_items_named_like_catalog_items_are_hidden() {
+        let catalog = Catalog::new(vec![item("Cake", 10)]).unwrap();
+        assert_eq!(catalog.item(" cake").map(|item| item.cost), Some(10));
+        let accented = Catalog::new(vec![item("Crème", 20)]).unwrap();
+        assert_eq!(accented.item("CRÈME").map(|item| item.cost), Some(20));
+
+        let personal = [
+            Item {
+                owner: Some(1),
+                ..item("CAKE", 1)
+            },
+            Item {
+                owner: Some(1),
+                ..item("Nap", 5)
+            },
+        ];
+        let names = catalog
+            .merged(&personal)
+            .into_iter()
+            .map(|item| item.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Cake", "Nap"]);
+    }
+
+    #[test]
+    fn restock_parse_reads_every_rule() {
+        assert_eq!(Restock::parse("daily"), Some(Restock::Daily));
+        assert_eq!(Restock::parse(" Weekly "), Some(Restock::Weekly));
+        assert_eq!(Restock::parse("MONTHLY"), Some(Restock::Monthly));
+        assert_eq!(Restock::parse("every  7 days"), Some(Restock::EveryDays(7)));
+        for restock in [
+            "",
+            "hourly",
+            "every 0 days",
+            "every -1 days",
+            "every day",
+            "every 3",
+        ] {
+            assert_eq!(Restock::parse(restock), None, "{:?}", restock);
+        }
+        for restock in [
+            Restock::Daily,
+            Restock::Weekly,
+            Restock::Monthly,
+            Restock::EveryDays(3),
+        ] {
+            assert_eq!(Restock::parse(&restock.to_string()), Some(restock));
+        }
+    }
+}
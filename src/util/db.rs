@@ -1,94 +1,1592 @@
-use super::Records;
-use rusqlite::{params, Connection, Result};
-#[derive(Debug)]
+pub mod migrations;
+
+use std::{
+    cell::Cell,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use super::{
+    catalog::{self, Item, Purchase, Restock},
+    events::{Action, Event, Origin},
+    penalty,
+    records::{
+        ChecklistItem, Completion, LegacyRecord, Priority, Proof, Record, Reward, Source, Task,
+        TaskKind, Transaction,
+    },
+    recurrence::Recurrence,
+    settings::Settings,
+    streaks::{self, StreakBonuses},
+    trash::{self, Trashed},
+    RecordId,
+};
+use rusqlite::{
+    backup::{Backup, StepResult},
+    params,
+    types::Type,
+    Connection, OpenFlags, OptionalExtension, Result, Row, TransactionBehavior,
+};
+
+/// How long to wait for another connection to release its lock before giving up
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a backup waits before retrying when the other database is locked
+const BACKUP_RETRY: Duration = Duration::from_millis(100);
+
+/// The kind of records a user owns
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordType {
+    Daily,
+    Pending,
+    Transaction,
+}
+
+impl RecordType {
+    /// The value stored in the `kind` column of `tasks`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecordType::Daily => "daily",
+            RecordType::Pending => "pending",
+            RecordType::Transaction => "transaction",
+        }
+    }
+
+    pub fn parse(record_type: &str) -> Option<Self> {
+        match record_type {
+            "daily" => Some(RecordType::Daily),
+            "pending" => Some(RecordType::Pending),
+            "transaction" => Some(RecordType::Transaction),
+            _ => None,
+        }
+    }
+
+    /// The kind of task this is, `None` for transactions
+    pub fn task_kind(&self) -> Option<TaskKind> {
+        match self {
+            RecordType::Daily => Some(TaskKind::Daily),
+            RecordType::Pending => Some(TaskKind::Pending),
+            RecordType::Transaction => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct User {
     pub id: u64,
-    pub daily: Records,
-    pub pending: Records,
-    pub transactions: Records,
+    pub daily: Vec<Task>,
+    pub pending: Vec<Task>,
+    pub transactions: Vec<Transaction>,
 }
 
 impl User {
     pub fn new(id: u64) -> Self {
         Self {
             id,
-            daily: Records::new(),
-            pending: Records::new(),
-            transactions: Records::new(),
+            daily: Vec::new(),
+            pending: Vec::new(),
+            transactions: Vec::new(),
         }
     }
 
-    pub fn from_file(db_file: &str, user_id: u64) -> Result<Self> {
-        match query_user(db_file, user_id)? {
+    /// Put records in the order the stores hand them out in: tasks by id, transactions by time
+    pub fn sort_records(&mut self) {
+        self.daily.sort_by_key(|task| task.id);
+        self.pending.sort_by_key(|task| task.id);
+        self.transactions
+            .sort_by_key(|transaction| (transaction.timestamp, transaction.id));
+    }
+
+    pub fn tasks_mut(&mut self, kind: TaskKind) -> &mut Vec<Task> {
+        match kind {
+            TaskKind::Daily => &mut self.daily,
+            TaskKind::Pending => &mut self.pending,
+        }
+    }
+
+    /// The record of a type with the given id, if the user has it
+    pub fn record(&self, record_type: RecordType, id: RecordId) -> Option<Record> {
+        match record_type.task_kind() {
+            Some(TaskKind::Daily) => self
+                .daily
+                .iter()
+                .find(|task| task.id == id)
+                .cloned()
+                .map(Record::Task),
+            Some(TaskKind::Pending) => self
+                .pending
+                .iter()
+                .find(|task| task.id == id)
+                .cloned()
+                .map(Record::Task),
+            None => self
+                .transactions
+                .iter()
+                .find(|transaction| transaction.id == id)
+                .cloned()
+                .map(Record::Transaction),
+        }
+    }
+
+    /// Insert a record, or overwrite the one with the same id. Returns what it overwrote.
+    pub fn put_record(&mut self, record: Record) -> Option<Record> {
+        let previous = self.remove_record(record.record_type(), record.id());
+        match record {
+            Record::Task(task) => self.tasks_mut(task.kind).push(task),
+            Record::Transaction(transaction) => self.transactions.push(transaction),
+        }
+        self.sort_records();
+        previous
+    }
+
+    /// Remove the record of a type with the given id, returning it if the user had it
+    pub fn remove_record(&mut self, record_type: RecordType, id: RecordId) -> Option<Record> {
+        match record_type.task_kind() {
+            Some(kind) => {
+                let tasks = self.tasks_mut(kind);
+                let position = tasks.iter().position(|task| task.id == id)?;
+                Some(Record::Task(tasks.remove(position)))
+            }
             None => {
-                insert_user(db_file, User::new(user_id))?;
-                Ok(Self::new(user_id))
+                let position = self
+                    .transactions
+                    .iter()
+                    .position(|transaction| transaction.id == id)?;
+                Some(Record::Transaction(self.transactions.remove(position)))
             }
-            Some(user) => Ok(user),
         }
     }
+}
+
+/// A handle to the database shared by every command. Cloning it is cheap, every clone
+/// talks to the same connection.
+#[derive(Clone)]
+pub struct Database(Arc<Mutex<Connection>>);
+
+impl Database {
+    /// Open the database in WAL mode, so other processes such as `db_util` can read and
+    /// write it while the bot is running, waiting out each other's locks instead of failing.
+    pub fn open(db_path: &str) -> Result<Self> {
+        let conn = Connection::open(db_path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        Ok(Self(Arc::new(Mutex::new(conn))))
+    }
 
-    pub fn update(&self, db_path: &str) -> Result<()> {
-        update_user(db_path, self)?;
+    /// Open a database that only lives as long as the returned handle, for tests
+    pub fn open_in_memory() -> Result<Self> {
+        let conn = Connection::open_in_memory()?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        Ok(Self(Arc::new(Mutex::new(conn))))
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.0.lock().expect("Database connection is poisoned")
+    }
+
+    /// Bring the database up to the latest schema, upgrading legacy rows along the way.
+    /// Returns the schema version before and after migrating.
+    pub fn migrate(&self) -> Result<(u32, u32)> {
+        let mut conn = self.lock();
+        let from = migrations::migrate(&mut conn)?;
+        Ok((from, migrations::schema_version(&conn)?))
+    }
+
+    /// Copy a consistent snapshot of the whole database to `path`, overwriting it.
+    /// Safe to call while the bot is running, writers simply wait until the copy is done.
+    pub fn backup_to(&self, path: &Path) -> Result<()> {
+        let conn = self.lock();
+        let mut dst = Connection::open(path)?;
+        copy_database(&conn, &mut dst)
+    }
+
+    /// Replace the contents of the database with the snapshot at `path`.
+    /// Run [`Database::migrate`] afterwards, the snapshot may predate the current schema.
+    pub fn restore_from(&self, path: &Path) -> Result<()> {
+        let mut conn = self.lock();
+        // Read only, so a mistyped path fails instead of restoring an empty database
+        let src = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        copy_database(&src, &mut conn)
+    }
+
+    pub fn insert_user(&self, user_id: u64) -> Result<()> {
+        ensure_user(&self.lock(), user_id)
+    }
+
+    pub fn query_user(&self, id: u64) -> Result<Option<User>> {
+        let conn = self.lock();
+
+        let exists = conn
+            .query_row("SELECT id FROM users WHERE id=?", [id], |row| {
+                row.get::<_, u64>(0)
+            })
+            .optional()?
+            .is_some();
+        if !exists {
+            return Ok(None);
+        }
+
+        Ok(Some(User {
+            id,
+            daily: query_tasks(&conn, id, TaskKind::Daily)?,
+            pending: query_tasks(&conn, id, TaskKind::Pending)?,
+            transactions: query_transactions(&conn, id)?,
+        }))
+    }
+
+    /// Fetch every task of a kind for a user, creating the user if they have never been seen
+    pub fn query_tasks(&self, user_id: u64, kind: TaskKind) -> Result<Vec<Task>> {
+        let conn = self.lock();
+        ensure_user(&conn, user_id)?;
+        query_tasks(&conn, user_id, kind)
+    }
+
+    /// Fetch every transaction of a user, creating the user if they have never been seen
+    pub fn query_transactions(&self, user_id: u64) -> Result<Vec<Transaction>> {
+        let conn = self.lock();
+        ensure_user(&conn, user_id)?;
+        query_transactions(&conn, user_id)
+    }
+
+    /// Every time a user completed a task, oldest first
+    pub fn query_completions(&self, user_id: u64, task_id: RecordId) -> Result<Vec<i64>> {
+        query_completions(&self.lock(), user_id, task_id)
+    }
+
+    /// Every time a user completed a task with whatever they attached as proof, oldest first
+    pub fn query_completion_history(
+        &self,
+        user_id: u64,
+        task_id: RecordId,
+    ) -> Result<Vec<Completion>> {
+        query_completion_history(&self.lock(), user_id, task_id)
+    }
+
+    /// Every proof a user attached, as `(task_id, completed, proof)`, oldest first
+    pub fn query_proofs(&self, user_id: u64) -> Result<Vec<(RecordId, i64, Proof)>> {
+        let conn = self.lock();
+        let mut stmt = conn.prepare(
+            "SELECT task_id, completed, note, attachment FROM completions
+                WHERE user_id=?1 AND (note IS NOT NULL OR attachment IS NOT NULL)
+                ORDER BY completed",
+        )?;
+        let proofs = stmt
+            .query_map([user_id], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .filter_map(|(task_id, completed, note, attachment)| {
+                Some((task_id, completed, Proof::new(note, attachment)?))
+            })
+            .collect();
+
+        Ok(proofs)
+    }
+
+    /// A user's settings, the defaults if they never changed any
+    pub fn query_settings(&self, user_id: u64) -> Result<Settings> {
+        query_settings(&self.lock(), user_id)
+    }
+
+    /// Replace a user's settings, creating the user if they have never been seen
+    pub fn update_settings(&self, user_id: u64, settings: Settings) -> Result<()> {
+        let conn = self.lock();
+        ensure_user(&conn, user_id)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO settings (user_id, utc_offset, timezone, day_start)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![
+                user_id,
+                settings.utc_offset,
+                settings.timezone.map(|timezone| timezone.name()),
+                settings.day_start
+            ],
+        )?;
         Ok(())
     }
+
+    /// The items a user added to their own shop, in the order they were added
+    pub fn query_shop_items(&self, user_id: u64) -> Result<Vec<Item>> {
+        query_shop_items(&self.lock(), user_id)
+    }
+
+    /// How many of an item were sold since it was last restocked, to anyone for catalog items
+    pub fn query_sold(&self, user_id: u64, item: &Item) -> Result<i64> {
+        let conn = self.lock();
+        query_sold(&conn, item, &query_settings(&conn, user_id)?)
+    }
+
+    /// Add an item to a user's own shop, creating the user if they have never been seen.
+    /// Returns `false` if they already have an item with that name.
+    pub fn insert_shop_item(&self, user_id: u64, item: &Item) -> Result<bool> {
+        let conn = self.lock();
+        ensure_user(&conn, user_id)?;
+        // The insert checks the name itself, so of two items added at once only one goes in
+        Ok(conn.execute(
+            "INSERT INTO shop_items
+                    (user_id, name, key, cost, qty, description, restock, available_from,
+                        available_until)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                ON CONFLICT DO NOTHING",
+            params![
+                user_id,
+                item.name,
+                catalog::name_key(&item.name),
+                item.cost,
+                item.qty,
+                item.description,
+                item.restock.map(String::from),
+                item.available_from.map(|from| from.to_string()),
+                item.available_until.map(|until| until.to_string())
+            ],
+        )? != 0)
+    }
+
+    /// Replace the item named `name` in a user's own shop, keeping its place and what was sold
+    /// of it since the last restock.
+    /// Returns `false` if they have no such item, or another item has the new name.
+    pub fn update_shop_item(&self, user_id: u64, name: &str, item: &Item) -> Result<bool> {
+        let mut conn = self.lock();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let id = match find_shop_item(&tx, user_id, name)? {
+            Some(id) => id,
+            None => return Ok(false),
+        };
+        if find_shop_item(&tx, user_id, &item.name)?.is_some_and(|other| other != id) {
+            return Ok(false);
+        }
+        tx.execute(
+            "UPDATE shop_items SET name=?1, key=?2, cost=?3, qty=?4, description=?5, restock=?6,
+                    available_from=?7, available_until=?8
+                WHERE id=?9",
+            params![
+                item.name,
+                catalog::name_key(&item.name),
+                item.cost,
+                item.qty,
+                item.description,
+                item.restock.map(String::from),
+                item.available_from.map(|from| from.to_string()),
+                item.available_until.map(|until| until.to_string()),
+                id
+            ],
+        )?;
+        // The stock follows the item to its new name
+        tx.execute(
+            "UPDATE OR REPLACE stock SET item=?3 WHERE owner_id=?1 AND item=?2",
+            params![
+                user_id,
+                catalog::name_key(name),
+                catalog::name_key(&item.name)
+            ],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Take the item named `name` out of a user's own shop, along with its stock.
+    /// Returns `false` if they have no such item.
+    pub fn delete_shop_item(&self, user_id: u64, name: &str) -> Result<bool> {
+        let mut conn = self.lock();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let deleted = tx.execute(
+            "DELETE FROM shop_items WHERE user_id=?1 AND key=?2",
+            params![user_id, catalog::name_key(name)],
+        )? != 0;
+        if deleted {
+            tx.execute(
+                "DELETE FROM stock WHERE owner_id=?1 AND item=?2",
+                params![user_id, catalog::name_key(name)],
+            )?;
+        }
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    /// The ids of every user
+    pub fn query_user_ids(&self) -> Result<Vec<u64>> {
+        let conn = self.lock();
+        let mut stmt = conn.prepare("SELECT id FROM users ORDER BY id")?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ids)
+    }
+
+    /// A user's audit log, oldest first
+    pub fn query_events(&self, user_id: u64) -> Result<Vec<Event>> {
+        let conn = self.lock();
+        let mut stmt = conn.prepare(
+            "SELECT id, user_id, actor_id, surface, action, record_type, record_id,
+                    name, points, record_timestamp, timestamp, payload
+                FROM events
+                WHERE user_id=?1
+                ORDER BY id",
+        )?;
+        let events = stmt
+            .query_map([user_id], |row| {
+                let action: String = row.get(4)?;
+                let record_type: String = row.get(5)?;
+                let record_type =
+                    RecordType::parse(&record_type).ok_or_else(|| invalid_text(5, record_type))?;
+                let record_id = row.get(6)?;
+                let legacy = match row.get::<_, Option<String>>(7)? {
+                    Some(name) => Some((name, row.get(8)?, row.get(9)?)),
+                    None => None,
+                };
+                Ok(Event {
+                    id: row.get(0)?,
+                    user_id: row.get(1)?,
+                    actor_id: row.get(2)?,
+                    surface: row.get(3)?,
+                    action: Action::parse(&action).ok_or_else(|| invalid_text(4, action))?,
+                    record_type,
+                    record_id,
+                    record: match legacy {
+                        Some(legacy) => {
+                            Some(stored_record(row, 11, record_type, record_id, legacy)?)
+                        }
+                        None => None,
+                    },
+                    timestamp: row.get(10)?,
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(events)
+    }
+
+    /// Everything in a user's trash that has not expired yet, newest first
+    pub fn query_trash(&self, user_id: u64) -> Result<Vec<Trashed>> {
+        query_trash(&self.lock(), user_id)
+    }
+
+    /// Run `f` against a user's records inside a single write transaction. Every change `f`
+    /// makes is committed together, and nothing else can write to the database while it runs,
+    /// so concurrent commands can never overwrite each other's changes.
+    /// Each change is recorded in the audit log as coming from `origin`.
+    pub fn with_user_mut<T, F>(&self, user_id: u64, origin: Origin, f: F) -> Result<T>
+    where
+        F: FnOnce(&UserMut) -> Result<T>,
+    {
+        let mut conn = self.lock();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        ensure_user(&tx, user_id)?;
+
+        let result = f(&UserMut {
+            tx: &tx,
+            user_id,
+            origin,
+            batch: Cell::new(None),
+        })?;
+        tx.commit()?;
+
+        Ok(result)
+    }
+}
+
+/// A user's records, borrowed for the duration of a [`Database::with_user_mut`] transaction
+pub struct UserMut<'a> {
+    tx: &'a rusqlite::Transaction<'a>,
+    user_id: u64,
+    origin: Origin,
+    /// The trash batch of this transaction, picked when the first record is trashed
+    batch: Cell<Option<i64>>,
 }
 
-pub fn insert_user(db_path: &str, user: User) -> Result<()> {
-    let conn = Connection::open(db_path)?;
-    conn.prepare(
-        "CREATE TABLE IF NOT EXISTS users (
-                        id              INTEGER PRIMARY KEY,
-                        daily           BLOB,
-                        periodic        BLOB,
-                        transactions    BLOB)",
-    )?
-    .execute([])?;
+impl UserMut<'_> {
+    /// Fetch every task of a kind, as it is within this transaction
+    pub fn tasks(&self, kind: TaskKind) -> Result<Vec<Task>> {
+        query_tasks(self.tx, self.user_id, kind)
+    }
+
+    /// Fetch every transaction, as it is within this transaction
+    pub fn transactions(&self) -> Result<Vec<Transaction>> {
+        query_transactions(self.tx, self.user_id)
+    }
+
+    /// The record of a type with the given id, if this user has it
+    pub fn record(&self, record_type: RecordType, id: RecordId) -> Result<Option<Record>> {
+        Ok(match record_type.task_kind() {
+            Some(kind) => self
+                .tasks(kind)?
+                .into_iter()
+                .find(|task| task.id == id)
+                .map(Record::Task),
+            None => self
+                .transactions()?
+                .into_iter()
+                .find(|transaction| transaction.id == id)
+                .map(Record::Transaction),
+        })
+    }
+
+    /// Store a new record, ignoring whatever id it has. Returns its new id.
+    pub fn insert_record(&self, mut record: Record) -> Result<RecordId> {
+        let id = match &record {
+            Record::Task(task) => insert_task(self.tx, self.user_id, None, task)?,
+            Record::Transaction(transaction) => {
+                insert_transaction(self.tx, self.user_id, None, transaction)?
+            }
+        };
+        record.set_id(id);
+
+        self.log(Action::Insert, record.record_type(), id, Some(&record))?;
+        Ok(id)
+    }
+
+    /// Overwrite the record with the same id, moving its previous contents to the trash.
+    /// Returns `false` if this user has no such record.
+    pub fn update_record(&self, mut record: Record) -> Result<bool> {
+        let (record_type, id) = (record.record_type(), record.id());
+        let previous = match self.record(record_type, id)? {
+            Some(previous) => previous,
+            None => return Ok(false),
+        };
+        record.keep_provenance(&previous);
+
+        self.trash(Action::Update, &previous, &[])?;
+        write_record(self.tx, self.user_id, &record)?;
+        self.log(Action::Update, record_type, id, Some(&record))?;
+        Ok(true)
+    }
+
+    /// Move the record with the given id to the trash.
+    /// Returns `false` if this user has no such record.
+    pub fn delete_record(&self, record_type: RecordType, id: RecordId) -> Result<bool> {
+        let previous = match self.record(record_type, id)? {
+            Some(previous) => previous,
+            None => return Ok(false),
+        };
+
+        // Deleting the task deletes its completions too, so the trash keeps them
+        let completions = match &previous {
+            Record::Task(task) => query_completion_history(self.tx, self.user_id, task.id)?,
+            Record::Transaction(_) => Vec::new(),
+        };
+        self.trash(Action::Delete, &previous, &completions)?;
+        match record_type.task_kind() {
+            Some(kind) => self.tx.execute(
+                "DELETE FROM tasks WHERE id=?1 AND user_id=?2 AND kind=?3",
+                params![id, self.user_id, RecordType::from(kind).as_str()],
+            )?,
+            None => self.tx.execute(
+                "DELETE FROM transactions WHERE id=?1 AND user_id=?2",
+                params![id, self.user_id],
+            )?,
+        };
+        self.log(Action::Delete, record_type, id, None)?;
+        Ok(true)
+    }
+
+    /// Everything in this user's trash that has not expired yet, newest first
+    pub fn trashed(&self) -> Result<Vec<Trashed>> {
+        query_trash(self.tx, self.user_id)
+    }
 
-    let mut stmt =
-        conn.prepare("INSERT INTO users (id,daily,periodic,transactions) VALUES (?1, ?2, ?3, ?4)")?;
-    stmt.execute(params![
-        user.id,
-        user.daily,
-        user.pending,
-        user.transactions
-    ])?;
+    /// Put back every record trashed in `batch` and empty it from the trash. Deleted records
+    /// are inserted again, keeping their ids if nothing has taken them since, and overwritten
+    /// ones get their previous contents back. Returns the restored records.
+    pub fn restore(&self, batch: i64) -> Result<Vec<Trashed>> {
+        let mut restored = Vec::new();
 
+        // Newest first, so a record trashed twice in a batch ends up as it was before it
+        for mut entry in self
+            .trashed()?
+            .into_iter()
+            .filter(|entry| entry.batch == batch)
+        {
+            let record_type = entry.record.record_type();
+            let current = match entry.action {
+                Action::Update => self.record(record_type, entry.record.id())?,
+                _ => None,
+            };
+            let overwritten = match current {
+                Some(current) => {
+                    entry.record.keep_progress(&current);
+                    write_record(self.tx, self.user_id, &entry.record)?
+                }
+                None => false,
+            };
+
+            if !overwritten {
+                let id = (!id_taken(self.tx, record_type, entry.record.id())?)
+                    .then_some(entry.record.id());
+                let id = match &entry.record {
+                    Record::Task(task) => insert_task(self.tx, self.user_id, id, task)?,
+                    Record::Transaction(transaction) => {
+                        insert_transaction(self.tx, self.user_id, id, transaction)?
+                    }
+                };
+                entry.record.set_id(id);
+                if !entry.completions.is_empty() {
+                    restore_completions(self.tx, self.user_id, id, &entry.completions)?;
+                }
+            }
+
+            self.log(
+                Action::Restore,
+                record_type,
+                entry.record.id(),
+                Some(&entry.record),
+            )?;
+            restored.push(entry);
+        }
+
+        self.tx.execute(
+            "DELETE FROM trash WHERE user_id=?1 AND batch=?2",
+            params![self.user_id, batch],
+        )?;
+        Ok(restored)
+    }
+
+    /// Restore whatever was trashed most recently
+    pub fn undo(&self) -> Result<Vec<Trashed>> {
+        match self.trashed()?.first() {
+            Some(latest) => self.restore(latest.batch),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Mark a task as completed now and award its points, and the streak bonus it earned.
+    /// Returns the completion timestamp, or `None` if this user has no such task.
+    pub fn complete_task(
+        &self,
+        kind: TaskKind,
+        id: RecordId,
+        bonuses: &StreakBonuses,
+    ) -> Result<Option<i64>> {
+        let mut task = match self.record(kind.into(), id)? {
+            Some(Record::Task(task)) => task,
+            _ => return Ok(None),
+        };
+
+        let timestamp = chrono::Utc::now().timestamp();
+        self.complete(&mut task, timestamp)?;
+
+        self.log(
+            Action::Complete,
+            kind.into(),
+            id,
+            Some(&Record::Task(task.clone())),
+        )?;
+        self.pay(&task, task.points, timestamp)?;
+        self.pay_bonus(&task, bonuses)?;
+        Ok(Some(timestamp))
+    }
+
+    /// Log `amount` more towards a task's target and pay whatever that earned, forgetting
+    /// progress that no longer counts. Reaching the target completes the task.
+    /// Returns the task's progress, or `None` if this user has no such task.
+    pub fn add_progress(
+        &self,
+        kind: TaskKind,
+        id: RecordId,
+        amount: u32,
+        bonuses: &StreakBonuses,
+    ) -> Result<Option<u32>> {
+        let mut task = match self.record(kind.into(), id)? {
+            Some(Record::Task(task)) => task,
+            _ => return Ok(None),
+        };
+
+        let settings = query_settings(self.tx, self.user_id)?;
+        let target = task.target.unwrap_or(1);
+        let from = task.progress_since(task.counting_since(&settings));
+        let to = (from + amount).min(target);
+        if to == from {
+            return Ok(Some(to));
+        }
+
+        let timestamp = chrono::Utc::now().timestamp();
+        task.progress = to;
+        task.progress_at = Some(timestamp);
+        let action = if to == target {
+            self.complete(&mut task, timestamp)?;
+            Action::Complete
+        } else {
+            Action::Progress
+        };
+        write_record(self.tx, self.user_id, &Record::Task(task.clone()))?;
+
+        self.log(action, kind.into(), id, Some(&Record::Task(task.clone())))?;
+        self.pay(&task, task.payout(from, to), timestamp)?;
+        if action == Action::Complete {
+            self.pay_bonus(&task, bonuses)?;
+        }
+        Ok(Some(to))
+    }
+
+    /// Check off an item of a task's checklist and pay whatever that earned. Checking the last
+    /// one completes the task.
+    /// Returns how many items are checked, or `None` if this user has no such task or item.
+    pub fn check_item(
+        &self,
+        kind: TaskKind,
+        id: RecordId,
+        item: usize,
+        bonuses: &StreakBonuses,
+    ) -> Result<Option<u32>> {
+        let mut task = match self.record(kind.into(), id)? {
+            Some(Record::Task(task)) if item < task.checklist.len() => task,
+            _ => return Ok(None),
+        };
+
+        let from = task.checked();
+        if task.checklist[item].done_at.is_some() {
+            return Ok(Some(from));
+        }
+
+        let timestamp = chrono::Utc::now().timestamp();
+        task.checklist[item].done_at = Some(timestamp);
+        let to = task.checked();
+        let action = if to == task.checklist.len() as u32 {
+            self.complete(&mut task, timestamp)?;
+            Action::Complete
+        } else {
+            Action::Progress
+        };
+        write_record(self.tx, self.user_id, &Record::Task(task.clone()))?;
+
+        self.log(action, kind.into(), id, Some(&Record::Task(task.clone())))?;
+        self.pay(&task, task.payout(from, to), timestamp)?;
+        if action == Action::Complete {
+            self.pay_bonus(&task, bonuses)?;
+        }
+        Ok(Some(to))
+    }
+
+    /// Buy an item if it is for sale and the user can afford it, paying with a transaction
+    /// such as `Bought: Movie night -300`
+    pub fn buy(&self, item: &Item) -> Result<Purchase> {
+        let transactions = self.transactions()?;
+        let settings = query_settings(self.tx, self.user_id)?;
+        let sold = query_sold(self.tx, item, &settings)?;
+        if let Some(refusal) = catalog::refusal(item, &transactions, sold, &settings) {
+            return Ok(refusal);
+        }
+        if item.qty.is_some() {
+            // A stock left over from an earlier restock period starts over
+            self.tx.execute(
+                "INSERT INTO stock (owner_id, item, restocked, sold) VALUES (?1, ?2, ?3, 1)
+                    ON CONFLICT (owner_id, item) DO UPDATE SET
+                        sold = CASE WHEN restocked = excluded.restocked THEN sold + 1 ELSE 1 END,
+                        restocked = excluded.restocked",
+                params![
+                    item.owner.unwrap_or(0),
+                    catalog::name_key(&item.name),
+                    item.restocked(&settings)
+                ],
+            )?;
+        }
+        let balance = transactions
+            .iter()
+            .map(|transaction| transaction.amount)
+            .sum::<i64>();
+
+        let mut transaction = Transaction::new(
+            format!("Bought: {}", item.name),
+            -item.cost,
+            Source::Purchase,
+        );
+        transaction.reward = Some(Reward {
+            item: item.name.clone(),
+            redeemed_at: None,
+        });
+        transaction.id = insert_transaction(self.tx, self.user_id, None, &transaction)?;
+        self.log(
+            Action::Insert,
+            RecordType::Transaction,
+            transaction.id,
+            Some(&Record::Transaction(transaction)),
+        )?;
+        Ok(Purchase::Bought {
+            balance: balance - item.cost,
+        })
+    }
+
+    /// Mark a bought reward as used.
+    /// Returns `false` if this user has no such reward, or already redeemed it.
+    pub fn redeem(&self, id: RecordId) -> Result<bool> {
+        let mut transaction = match self.record(RecordType::Transaction, id)? {
+            Some(Record::Transaction(transaction)) if transaction.is_owned() => transaction,
+            _ => return Ok(false),
+        };
+
+        if let Some(reward) = &mut transaction.reward {
+            reward.redeemed_at = Some(chrono::Utc::now().timestamp());
+        }
+        write_record(
+            self.tx,
+            self.user_id,
+            &Record::Transaction(transaction.clone()),
+        )?;
+        self.log(
+            Action::Redeem,
+            RecordType::Transaction,
+            id,
+            Some(&Record::Transaction(transaction)),
+        )?;
+        Ok(true)
+    }
+
+    /// Attach a note or an attachment to the completion of a task at `completed`.
+    /// Returns `false` if this user has no such task, it was completed again since, or that
+    /// completion already has proof.
+    pub fn attach_proof(
+        &self,
+        kind: TaskKind,
+        id: RecordId,
+        completed: i64,
+        proof: Proof,
+    ) -> Result<bool> {
+        let mut task = match self.record(kind.into(), id)? {
+            Some(Record::Task(task))
+                if task.completed_at == Some(completed) && task.proof.is_none() =>
+            {
+                task
+            }
+            _ => return Ok(false),
+        };
+
+        task.proof = Some(proof);
+        write_record(self.tx, self.user_id, &Record::Task(task.clone()))?;
+        self.log(Action::Attach, kind.into(), id, Some(&Record::Task(task)))?;
+        Ok(true)
+    }
+
+    /// Add a completion at `timestamp` to a task. Writing the task afterwards keeps it, rather
+    /// than replacing its previous completion.
+    fn complete(&self, task: &mut Task, timestamp: i64) -> Result<()> {
+        self.tx.execute(
+            "INSERT INTO completions (user_id, task_id, completed) VALUES (?1, ?2, ?3)",
+            params![self.user_id, task.id, timestamp],
+        )?;
+        task.complete(timestamp);
+        Ok(())
+    }
+
+    /// Pay the streak bonus a task earned by being completed just now, if it earned one
+    fn pay_bonus(&self, task: &Task, bonuses: &StreakBonuses) -> Result<()> {
+        if task.kind != TaskKind::Daily {
+            return Ok(());
+        }
+        let completions = query_completions(self.tx, self.user_id, task.id)?;
+        let settings = query_settings(self.tx, self.user_id)?;
+        let mut transaction = match streaks::earned_bonus(
+            task,
+            &completions,
+            &self.transactions()?,
+            bonuses,
+            &settings,
+        ) {
+            Some(transaction) => transaction,
+            None => return Ok(()),
+        };
+
+        transaction.id = insert_transaction(self.tx, self.user_id, None, &transaction)?;
+        self.log(
+            Action::Insert,
+            RecordType::Transaction,
+            transaction.id,
+            Some(&Record::Transaction(transaction)),
+        )
+    }
+
+    /// Pay the points a task earned, unless it earned none
+    fn pay(&self, task: &Task, amount: i64, timestamp: i64) -> Result<()> {
+        if amount == 0 {
+            return Ok(());
+        }
+
+        let mut transaction = Transaction::new(task.title.clone(), amount, Source::Task(task.id));
+        transaction.timestamp = timestamp;
+        transaction.tags = task.tags.clone();
+        transaction.id = insert_transaction(self.tx, self.user_id, None, &transaction)?;
+        self.log(
+            Action::Insert,
+            RecordType::Transaction,
+            transaction.id,
+            Some(&Record::Transaction(transaction)),
+        )
+    }
+
+    /// Take `penalty` points for every pending task that went overdue, once per task.
+    /// Returns the penalties taken just now.
+    pub fn penalize_overdue(&self, penalty: i64) -> Result<Vec<Transaction>> {
+        let now = chrono::Utc::now().timestamp();
+        let penalized = self
+            .transactions()?
+            .into_iter()
+            .filter_map(|transaction| match transaction.source {
+                Source::Overdue(task_id) => Some(task_id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut penalties = Vec::new();
+        for task in self.tasks(TaskKind::Pending)? {
+            if !task.is_overdue(now) || penalized.contains(&task.id) {
+                continue;
+            }
+
+            let mut transaction = penalty::overdue_penalty(&task, penalty);
+            transaction.id = insert_transaction(self.tx, self.user_id, None, &transaction)?;
+            self.log(
+                Action::Insert,
+                RecordType::Transaction,
+                transaction.id,
+                Some(&Record::Transaction(transaction.clone())),
+            )?;
+            penalties.push(transaction);
+        }
+        Ok(penalties)
+    }
+
+    /// Take the missed-day penalty of every daily task that was not completed in a period
+    /// ending on a day that went by since the last check, once per task and day.
+    /// Returns the penalties taken just now.
+    pub fn penalize_missed(&self) -> Result<Vec<Transaction>> {
+        let settings = query_settings(self.tx, self.user_id)?;
+        let through = self
+            .tx
+            .query_row(
+                "SELECT missed_through FROM users WHERE id=?1",
+                [self.user_id],
+                |row| row.get::<_, Option<String>>(0),
+            )?
+            .and_then(|day| day.parse().ok());
+        let days = penalty::unchecked_days(through, &settings);
+        let last = match days.last() {
+            Some(last) => *last,
+            None => return Ok(Vec::new()),
+        };
+        let transactions = self.transactions()?;
+
+        let mut penalties = Vec::new();
+        for task in self.tasks(TaskKind::Daily)? {
+            if task.penalty <= 0 {
+                continue;
+            }
+            let completions = query_completions(self.tx, self.user_id, task.id)?;
+            for &day in &days {
+                if transactions.iter().any(|transaction| {
+                    penalty::is_missed_penalty(transaction, task.id, day, &settings)
+                }) || !penalty::missed(&task, &completions, day, &settings)
+                {
+                    continue;
+                }
+
+                let mut transaction = penalty::missed_penalty(&task, day, &settings);
+                transaction.id = insert_transaction(self.tx, self.user_id, None, &transaction)?;
+                self.log(
+                    Action::Insert,
+                    RecordType::Transaction,
+                    transaction.id,
+                    Some(&Record::Transaction(transaction.clone())),
+                )?;
+                penalties.push(transaction);
+            }
+        }
+
+        self.tx.execute(
+            "UPDATE users SET missed_through=?2 WHERE id=?1",
+            params![self.user_id, last.to_string()],
+        )?;
+        Ok(penalties)
+    }
+
+    /// Keep a copy of a record that is about to be deleted or overwritten, along with the
+    /// completions that go with it
+    fn trash(&self, action: Action, record: &Record, completions: &[Completion]) -> Result<()> {
+        let batch = match self.batch.get() {
+            Some(batch) => batch,
+            None => {
+                let batch = self.tx.query_row(
+                    "SELECT IFNULL(MAX(batch), 0) + 1 FROM trash",
+                    [],
+                    |row| row.get(0),
+                )?;
+                self.batch.set(Some(batch));
+                batch
+            }
+        };
+
+        self.tx.execute(
+            "INSERT INTO trash (user_id, batch, action, record_type, record_id,
+                    name, points, record_timestamp, trashed, payload, completions)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                self.user_id,
+                batch,
+                action.as_str(),
+                record.record_type().as_str(),
+                record.id(),
+                record.name(),
+                record.points(),
+                record.timestamp(),
+                chrono::Utc::now().timestamp(),
+                payload(record),
+                (!completions.is_empty()).then(|| {
+                    serde_json::to_string(completions).expect("Unable to serialize completions")
+                })
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Append a change to the audit log
+    fn log(
+        &self,
+        action: Action,
+        record_type: RecordType,
+        record_id: RecordId,
+        record: Option<&Record>,
+    ) -> Result<()> {
+        self.tx.execute(
+            "INSERT INTO events (user_id, actor_id, surface, action, record_type, record_id,
+                    name, points, record_timestamp, timestamp, payload)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                self.user_id,
+                self.origin.actor_id,
+                self.origin.surface.as_str(),
+                action.as_str(),
+                record_type.as_str(),
+                record_id,
+                record.map(|record| record.name()),
+                record.map(|record| record.points()),
+                record.and_then(|record| record.timestamp()),
+                chrono::Utc::now().timestamp(),
+                record.map(payload)
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+/// The full record, as kept in the `payload` column of `events` and `trash`
+fn payload(record: &Record) -> String {
+    serde_json::to_string(record).expect("Unable to serialize record")
+}
+
+/// Read a record kept in `events` or `trash`. Rows written before the `payload` column
+/// existed only have the legacy name, points and timestamp columns, so fall back to those.
+fn stored_record(
+    row: &Row,
+    payload_column: usize,
+    record_type: RecordType,
+    record_id: RecordId,
+    legacy: LegacyRecord,
+) -> Result<Record> {
+    match row.get::<_, Option<String>>(payload_column)? {
+        Some(payload) => serde_json::from_str(&payload).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(payload_column, Type::Text, Box::new(e))
+        }),
+        None => Ok(Record::from_legacy(record_type, record_id, legacy)),
+    }
+}
+
+fn invalid_text(column: usize, value: String) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(column, Type::Text, value.into())
+}
+
+/// Copy every page of `src` over `dst` with the online backup API
+fn copy_database(src: &Connection, dst: &mut Connection) -> Result<()> {
+    let backup = Backup::new(src, dst)?;
+    // Copying every page in a single step keeps the snapshot consistent
+    while backup.step(-1)? != StepResult::Done {
+        thread::sleep(BACKUP_RETRY);
+    }
+    Ok(())
+}
+
+fn ensure_user(conn: &Connection, user_id: u64) -> Result<()> {
+    conn.execute("INSERT OR IGNORE INTO users (id) VALUES (?1)", [user_id])?;
     Ok(())
 }
 
-pub fn update_user(db_path: &str, user: &User) -> Result<()> {
-    let conn = Connection::open(db_path)?;
+fn query_tasks(conn: &Connection, user_id: u64, kind: TaskKind) -> Result<Vec<Task>> {
+    let mut stmt = conn.prepare(
+        "SELECT tasks.id, tasks.title, tasks.points, tasks.created_at,
+                MAX(completions.completed), tasks.notes, tasks.recurrence, tasks.due_at,
+                tasks.target, tasks.progress, tasks.progress_at, tasks.partial, tasks.tags,
+                tasks.penalty, tasks.priority, completions.note, completions.attachment
+            FROM tasks LEFT JOIN completions ON completions.task_id = tasks.id
+            WHERE tasks.user_id=?1 AND tasks.kind=?2
+            GROUP BY tasks.id
+            ORDER BY tasks.id",
+    )?;
+    let mut tasks = stmt
+        .query_map(params![user_id, RecordType::from(kind).as_str()], |row| {
+            let recurrence: String = row.get(6)?;
+            let priority: String = row.get(14)?;
+            Ok(Task {
+                id: row.get(0)?,
+                title: row.get(1)?,
+                points: row.get(2)?,
+                created_at: row.get(3)?,
+                completed_at: row.get(4)?,
+                notes: row.get(5)?,
+                kind,
+                recurrence: Recurrence::parse(&recurrence)
+                    .ok_or_else(|| invalid_text(6, recurrence))?,
+                due_at: row.get(7)?,
+                target: row.get(8)?,
+                progress: row.get(9)?,
+                progress_at: row.get(10)?,
+                partial: row.get(11)?,
+                checklist: Vec::new(),
+                tags: split_tags(&row.get::<_, String>(12)?),
+                penalty: row.get(13)?,
+                priority: Priority::parse(&priority).ok_or_else(|| invalid_text(14, priority))?,
+                // SQLite takes bare columns from the row MAX() picked, the latest completion
+                proof: Proof::new(row.get(15)?, row.get(16)?),
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    for task in &mut tasks {
+        task.checklist = query_checklist(conn, task.id)?;
+    }
+    Ok(tasks)
+}
+
+fn query_settings(conn: &Connection, user_id: u64) -> Result<Settings> {
+    Ok(conn
+        .query_row(
+            "SELECT utc_offset, timezone, day_start FROM settings WHERE user_id=?1",
+            [user_id],
+            |row| {
+                // A zone the timezone database no longer knows falls back to the fixed offset
+                let timezone: Option<String> = row.get(1)?;
+                Ok(Settings {
+                    utc_offset: row.get(0)?,
+                    timezone: timezone.as_deref().and_then(Settings::parse_timezone),
+                    day_start: row.get(2)?,
+                })
+            },
+        )
+        .optional()?
+        .unwrap_or_default())
+}
+
+/// How many of an item were sold since it was last restocked, as a user with `settings` sees it
+fn query_sold(conn: &Connection, item: &Item, settings: &Settings) -> Result<i64> {
+    if item.qty.is_none() {
+        return Ok(0);
+    }
+    let sold = conn
+        .query_row(
+            "SELECT sold FROM stock WHERE owner_id=?1 AND item=?2 AND restocked=?3",
+            params![
+                item.owner.unwrap_or(0),
+                catalog::name_key(&item.name),
+                item.restocked(settings)
+            ],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(sold.unwrap_or(0))
+}
+
+fn query_shop_items(conn: &Connection, user_id: u64) -> Result<Vec<Item>> {
+    let mut stmt = conn.prepare(
+        "SELECT name, cost, qty, description, restock, available_from, available_until
+            FROM shop_items
+            WHERE user_id=?1
+            ORDER BY id",
+    )?;
+    let date = |column: usize, date: Option<String>| {
+        date.map(|date| date.parse().map_err(|_| invalid_text(column, date)))
+            .transpose()
+    };
+    let items = stmt
+        .query_map([user_id], |row| {
+            Ok(Item {
+                name: row.get(0)?,
+                cost: row.get(1)?,
+                qty: row.get(2)?,
+                description: row.get(3)?,
+                restock: row
+                    .get::<_, Option<String>>(4)?
+                    .map(|restock| Restock::parse(&restock).ok_or_else(|| invalid_text(4, restock)))
+                    .transpose()?,
+                available_from: date(5, row.get(5)?)?,
+                available_until: date(6, row.get(6)?)?,
+                owner: Some(user_id),
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(items)
+}
+
+/// The id of a user's own shop item, by its name ignoring case
+fn find_shop_item(conn: &Connection, user_id: u64, name: &str) -> Result<Option<i64>> {
+    conn.query_row(
+        "SELECT id FROM shop_items WHERE user_id=?1 AND key=?2",
+        params![user_id, catalog::name_key(name)],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+fn query_completions(conn: &Connection, user_id: u64, task_id: RecordId) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT completed FROM completions
+            WHERE user_id=?1 AND task_id=?2
+            ORDER BY completed",
+    )?;
+    let completions = stmt
+        .query_map(params![user_id, task_id], |row| row.get(0))?
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(completions)
+}
+
+/// Every completion of a task with whatever proof was attached to it, oldest first
+fn query_completion_history(
+    conn: &Connection,
+    user_id: u64,
+    task_id: RecordId,
+) -> Result<Vec<Completion>> {
+    let mut stmt = conn.prepare(
+        "SELECT completed, note, attachment FROM completions
+            WHERE user_id=?1 AND task_id=?2
+            ORDER BY completed",
+    )?;
+    let completions = stmt
+        .query_map(params![user_id, task_id], |row| {
+            Ok(Completion {
+                completed: row.get(0)?,
+                proof: Proof::new(row.get(1)?, row.get(2)?),
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(completions)
+}
+
+/// Replace a restored task's completions with the ones it had when it was deleted
+fn restore_completions(
+    conn: &Connection,
+    user_id: u64,
+    task_id: RecordId,
+    completions: &[Completion],
+) -> Result<()> {
+    conn.execute("DELETE FROM completions WHERE task_id=?1", [task_id])?;
+    for completion in completions {
+        let (note, attachment) = completion.proof.as_ref().map_or((None, None), |proof| {
+            (proof.note.as_deref(), proof.attachment.as_deref())
+        });
+        conn.execute(
+            "INSERT INTO completions (user_id, task_id, completed, note, attachment)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![user_id, task_id, completion.completed, note, attachment],
+        )?;
+    }
+    Ok(())
+}
+
+/// Tags as they are stored in the `tags` columns. Tags never contain commas.
+fn join_tags(tags: &[String]) -> String {
+    tags.join(",")
+}
+
+fn split_tags(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
 
-    let mut stmt = conn.prepare(&format!(
-        "UPDATE users SET daily=?1,
-                          periodic=?2,
-                          transactions=?3
-            WHERE id={}",
-        user.id
-    ))?;
+/// A task's checklist, in order
+fn query_checklist(conn: &Connection, task_id: RecordId) -> Result<Vec<ChecklistItem>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT title, done_at FROM checklist_items WHERE task_id=?1 ORDER BY position",
+    )?;
+    let items = stmt
+        .query_map([task_id], |row| {
+            Ok(ChecklistItem {
+                title: row.get(0)?,
+                done_at: row.get(1)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
 
-    stmt.execute(params![user.daily, user.pending, user.transactions])?;
+    Ok(items)
+}
 
+/// Replace a task's stored checklist with the one it carries
+fn sync_checklist(conn: &Connection, task: &Task) -> Result<()> {
+    conn.execute("DELETE FROM checklist_items WHERE task_id=?1", [task.id])?;
+    for (position, item) in task.checklist.iter().enumerate() {
+        conn.execute(
+            "INSERT INTO checklist_items (task_id, position, title, done_at)
+                VALUES (?1, ?2, ?3, ?4)",
+            params![task.id, position, item.title, item.done_at],
+        )?;
+    }
     Ok(())
 }
 
-pub fn query_user(db_path: &str, id: u64) -> Result<Option<User>> {
-    let conn = Connection::open(db_path)?;
+fn query_transactions(conn: &Connection, user_id: u64) -> Result<Vec<Transaction>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, reason, amount, timestamp, source, task_id, tags, item, redeemed_at
+            FROM transactions
+            WHERE user_id=?1
+            ORDER BY timestamp, id",
+    )?;
+    let transactions = stmt
+        .query_map([user_id], |row| {
+            let source: String = row.get(4)?;
+            Ok(Transaction {
+                id: row.get(0)?,
+                reason: row.get(1)?,
+                amount: row.get(2)?,
+                timestamp: row.get(3)?,
+                source: Source::parse(&source, row.get(5)?)
+                    .ok_or_else(|| invalid_text(4, source))?,
+                tags: split_tags(&row.get::<_, String>(6)?),
+                reward: match row.get(7)? {
+                    Some(item) => Some(Reward {
+                        item,
+                        redeemed_at: row.get(8)?,
+                    }),
+                    None => None,
+                },
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
 
-    let mut stmt = conn.prepare("SELECT id,daily,periodic,transactions FROM users WHERE id=?")?;
-    let user = stmt
-        .query_map([id], |row| {
-            Ok(User {
+    Ok(transactions)
+}
+
+/// Overwrite a record in place, everything but when it was created and where it came from.
+/// Returns `false` if the user has no such record.
+fn write_record(conn: &Connection, user_id: u64, record: &Record) -> Result<bool> {
+    match record {
+        Record::Transaction(transaction) => Ok(conn.execute(
+            "UPDATE transactions SET reason=?1, amount=?2, timestamp=?3, tags=?4, item=?5,
+                    redeemed_at=?6
+                WHERE id=?7 AND user_id=?8",
+            params![
+                transaction.reason,
+                transaction.amount,
+                transaction.timestamp,
+                join_tags(&transaction.tags),
+                transaction.reward.as_ref().map(|reward| &reward.item),
+                transaction
+                    .reward
+                    .as_ref()
+                    .and_then(|reward| reward.redeemed_at),
+                transaction.id,
+                user_id
+            ],
+        )? != 0),
+        Record::Task(task) => {
+            let updated = conn.execute(
+                "UPDATE tasks SET title=?1, points=?2, notes=?3, recurrence=?4, due_at=?5,
+                        target=?6, progress=?7, progress_at=?8, partial=?9, tags=?10,
+                        penalty=?11, priority=?12
+                    WHERE id=?13 AND user_id=?14 AND kind=?15",
+                params![
+                    task.title,
+                    task.points,
+                    task.notes,
+                    task.recurrence.to_string(),
+                    task.due_at,
+                    task.target,
+                    task.progress,
+                    task.progress_at,
+                    task.partial,
+                    join_tags(&task.tags),
+                    task.penalty,
+                    task.priority.as_str(),
+                    task.id,
+                    user_id,
+                    RecordType::from(task.kind).as_str()
+                ],
+            )?;
+            if updated == 0 {
+                return Ok(false);
+            }
+
+            sync_completion(conn, user_id, task)?;
+            sync_checklist(conn, task)?;
+            Ok(true)
+        }
+    }
+}
+
+/// Whether any user has a record of this type with the given id
+fn id_taken(conn: &Connection, record_type: RecordType, id: RecordId) -> Result<bool> {
+    let table = match record_type {
+        RecordType::Transaction => "transactions",
+        _ => "tasks",
+    };
+    Ok(conn
+        .query_row(
+            &format!("SELECT 1 FROM {} WHERE id=?1", table),
+            [id],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+/// Everything in a user's trash that has not expired yet, newest first.
+/// Expired entries are purged along the way.
+fn query_trash(conn: &Connection, user_id: u64) -> Result<Vec<Trashed>> {
+    conn.execute(
+        "DELETE FROM trash WHERE user_id=?1 AND trashed<?2",
+        params![user_id, trash::expiry()],
+    )?;
+
+    let mut stmt = conn.prepare(
+        "SELECT id, user_id, batch, action, record_type, record_id,
+                name, points, record_timestamp, trashed, payload, completions
+            FROM trash
+            WHERE user_id=?1
+            ORDER BY batch DESC, id DESC",
+    )?;
+    let trashed = stmt
+        .query_map([user_id], |row| {
+            let action: String = row.get(3)?;
+            let record_type: String = row.get(4)?;
+            let record_type =
+                RecordType::parse(&record_type).ok_or_else(|| invalid_text(4, record_type))?;
+            let legacy = (row.get(6)?, row.get(7)?, row.get(8)?);
+            let completions = match row.get::<_, Option<String>>(11)? {
+                Some(completions) => serde_json::from_str(&completions).map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(11, Type::Text, Box::new(e))
+                })?,
+                None => Vec::new(),
+            };
+            Ok(Trashed {
                 id: row.get(0)?,
-                daily: row.get(1)?,
-                pending: row.get(2)?,
-                transactions: row.get(3)?,
+                user_id: row.get(1)?,
+                batch: row.get(2)?,
+                action: Action::parse(&action).ok_or_else(|| invalid_text(3, action))?,
+                record: stored_record(row, 10, record_type, row.get(5)?, legacy)?,
+                completions,
+                trashed: row.get(9)?,
             })
         })?
-        .into_iter()
-        .map(|row| row.unwrap())
-        .last();
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(trashed)
+}
+
+/// Insert a task, under the given id if there is one
+fn insert_task(
+    conn: &Connection,
+    user_id: u64,
+    id: Option<RecordId>,
+    task: &Task,
+) -> Result<RecordId> {
+    conn.execute(
+        "INSERT INTO tasks
+                (id, user_id, kind, title, points, created_at, notes, recurrence, due_at,
+                    target, progress, progress_at, partial, tags, penalty, priority)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+        params![
+            id,
+            user_id,
+            RecordType::from(task.kind).as_str(),
+            task.title,
+            task.points,
+            task.created_at,
+            task.notes,
+            task.recurrence.to_string(),
+            task.due_at,
+            task.target,
+            task.progress,
+            task.progress_at,
+            task.partial,
+            join_tags(&task.tags),
+            task.penalty,
+            task.priority.as_str()
+        ],
+    )?;
+    let id = conn.last_insert_rowid();
+    let task = Task { id, ..task.clone() };
+    sync_completion(conn, user_id, &task)?;
+    sync_checklist(conn, &task)?;
+    Ok(id)
+}
+
+/// Make a task's latest completion the one it carries. Only the latest completion is part of
+/// the record, earlier ones are history and are left alone unless the task is no longer
+/// completed at all.
+fn sync_completion(conn: &Connection, user_id: u64, task: &Task) -> Result<()> {
+    if task.completed_at.is_none() {
+        conn.execute("DELETE FROM completions WHERE task_id=?1", [task.id])?;
+        return Ok(());
+    }
+
+    let latest: Option<i64> = conn.query_row(
+        "SELECT MAX(completed) FROM completions WHERE task_id=?1",
+        [task.id],
+        |row| row.get(0),
+    )?;
+    let (note, attachment) = task.proof.as_ref().map_or((None, None), |proof| {
+        (proof.note.as_deref(), proof.attachment.as_deref())
+    });
+    if latest == task.completed_at {
+        conn.execute(
+            "UPDATE completions SET note=?1, attachment=?2 WHERE task_id=?3 AND completed=?4",
+            params![note, attachment, task.id, latest],
+        )?;
+        return Ok(());
+    }
+
+    if let Some(latest) = latest {
+        conn.execute(
+            "DELETE FROM completions WHERE task_id=?1 AND completed=?2",
+            params![task.id, latest],
+        )?;
+    }
+    conn.execute(
+        "INSERT INTO completions (user_id, task_id, completed, note, attachment)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![user_id, task.id, task.completed_at, note, attachment],
+    )?;
+    Ok(())
+}
 
-    Ok(user)
+/// Insert a transaction, under the given id if there is one
+fn insert_transaction(
+    conn: &Connection,
+    user_id: u64,
+    id: Option<RecordId>,
+    transaction: &Transaction,
+) -> Result<RecordId> {
+    conn.execute(
+        "INSERT INTO transactions
+                (id, user_id, reason, amount, timestamp, source, task_id, tags, item,
+                    redeemed_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            id,
+            user_id,
+            transaction.reason,
+            transaction.amount,
+            transaction.timestamp,
+            transaction.source.as_str(),
+            transaction.source.task_id(),
+            join_tags(&transaction.tags),
+            transaction.reward.as_ref().map(|reward| &reward.item),
+            transaction
+                .reward
+                .as_ref()
+                .and_then(|reward| reward.redeemed_at)
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
 }
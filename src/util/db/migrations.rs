@@ -0,0 +1,673 @@
+//! Ordered schema migrations. The version of the schema a database is at is kept in the
+//! `schema_version` table, and every migration newer than it is applied in order.
+use chrono::Utc;
+use rusqlite::{params, Connection, Result};
+
+use crate::util::{
+    catalog,
+    records::{self, LegacyRecord},
+};
+
+/// A single step of the schema history
+struct Migration {
+    version: u32,
+    description: &'static str,
+    up: fn(&Connection) -> Result<()>,
+}
+
+/// Every migration, ordered by version. Append only; never edit a released migration.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "normalized tasks, completions and transactions tables",
+        up: normalized_tables,
+    },
+    Migration {
+        version: 2,
+        description: "move legacy bincode records into the normalized tables",
+        up: upgrade_legacy_blobs,
+    },
+    Migration {
+        version: 3,
+        description: "append-only audit log of every change to a user's records",
+        up: events_table,
+    },
+    Migration {
+        version: 4,
+        description: "per-user trash of deleted and overwritten records",
+        up: trash_table,
+    },
+    Migration {
+        version: 5,
+        description: "typed tasks and transactions",
+        up: typed_records,
+    },
+    Migration {
+        version: 6,
+        description: "recurrence rules on tasks",
+        up: task_recurrence,
+    },
+    Migration {
+        version: 7,
+        description: "due dates on tasks",
+        up: task_due_dates,
+    },
+    Migration {
+        version: 8,
+        description: "targets and progress on tasks",
+        up: task_progress,
+    },
+    Migration {
+        version: 9,
+        description: "checklists on tasks",
+        up: checklist_items,
+    },
+    Migration {
+        version: 10,
+        description: "tags on tasks and transactions",
+        up: record_tags,
+    },
+    Migration {
+        version: 11,
+        description: "missed-day penalties on tasks",
+        up: task_penalties,
+    },
+    Migration {
+        version: 12,
+        description: "per-user timezone and day start",
+        up: user_settings,
+    },
+    Migration {
+        version: 13,
+        description: "priorities on tasks",
+        up: task_priorities,
+    },
+    Migration {
+        version: 14,
+        description: "notes and attachments on completions",
+        up: completion_proofs,
+    },
+    Migration {
+        version: 15,
+        description: "rewards bought in the shop and when they were redeemed",
+        up: purchase_rewards,
+    },
+    Migration {
+        version: 16,
+        description: "shop items users added for themselves",
+        up: user_shop_items,
+    },
+    Migration {
+        version: 17,
+        description: "stock sold since each shop item was restocked",
+        up: shop_stock,
+    },
+    Migration {
+        version: 18,
+        description: "named timezones in user settings",
+        up: named_timezones,
+    },
+    Migration {
+        version: 19,
+        description: "completions of deleted tasks kept in the trash",
+        up: trashed_completions,
+    },
+    Migration {
+        version: 20,
+        description: "last day each user was checked for missed tasks",
+        up: missed_through,
+    },
+    Migration {
+        version: 21,
+        description: "shop items and stock matched by lowercased name",
+        up: name_keys,
+    },
+];
+
+/// The version the database is at, or 0 for a database that has never been migrated
+pub fn schema_version(conn: &Connection) -> Result<u32> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version         INTEGER PRIMARY KEY,
+            description     TEXT NOT NULL,
+            applied         INTEGER NOT NULL)",
+    )?;
+    conn.query_row(
+        "SELECT IFNULL(MAX(version), 0) FROM schema_version",
+        [],
+        |row| row.get(0),
+    )
+}
+
+/// Apply every pending migration, each in its own transaction.
+/// Returns the version the database was at before migrating.
+pub fn migrate(conn: &mut Connection) -> Result<u32> {
+    let from = schema_version(conn)?;
+
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > from)
+    {
+        let tx = conn.transaction()?;
+        (migration.up)(&tx)?;
+        tx.execute(
+            "INSERT INTO schema_version (version, description, applied) VALUES (?1, ?2, ?3)",
+            params![
+                migration.version,
+                migration.description,
+                Utc::now().timestamp()
+            ],
+        )?;
+        tx.commit()?;
+    }
+
+    Ok(from)
+}
+
+fn normalized_tables(conn: &Connection) -> Result<()> {
+    // `IF NOT EXISTS` everywhere: legacy databases already have a `users` table, and
+    // databases created before migrations existed may already have all of them.
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS users (
+            id              INTEGER PRIMARY KEY);
+
+        CREATE TABLE IF NOT EXISTS tasks (
+            id              INTEGER PRIMARY KEY,
+            user_id         INTEGER NOT NULL REFERENCES users(id),
+            kind            TEXT NOT NULL,
+            name            TEXT NOT NULL,
+            points          INTEGER NOT NULL);
+
+        CREATE TABLE IF NOT EXISTS completions (
+            id              INTEGER PRIMARY KEY,
+            user_id         INTEGER NOT NULL REFERENCES users(id),
+            task_id         INTEGER NOT NULL REFERENCES tasks(id) ON DELETE CASCADE,
+            completed       INTEGER NOT NULL);
+
+        CREATE TABLE IF NOT EXISTS transactions (
+            id              INTEGER PRIMARY KEY,
+            user_id         INTEGER NOT NULL REFERENCES users(id),
+            name            TEXT NOT NULL,
+            points          INTEGER NOT NULL,
+            timestamp       INTEGER NOT NULL);
+
+        CREATE INDEX IF NOT EXISTS tasks_user_kind ON tasks (user_id, kind);
+        CREATE INDEX IF NOT EXISTS completions_task ON completions (task_id, completed);
+        CREATE INDEX IF NOT EXISTS transactions_user ON transactions (user_id, timestamp);",
+    )
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>>>()?;
+    Ok(columns.iter().any(|name| name == column))
+}
+
+fn decode_legacy(blob: Option<Vec<u8>>) -> Result<Vec<LegacyRecord>> {
+    match blob {
+        Some(blob) => records::decode_legacy(&blob).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Blob, Box::new(e))
+        }),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn upgrade_legacy_blobs(conn: &Connection) -> Result<()> {
+    if !has_column(conn, "users", "daily")? {
+        return Ok(());
+    }
+
+    let mut stmt = conn.prepare("SELECT id, daily, periodic, transactions FROM users")?;
+    let users = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, Option<Vec<u8>>>(1)?,
+                row.get::<_, Option<Vec<u8>>>(2)?,
+                row.get::<_, Option<Vec<u8>>>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    for (user_id, daily, pending, transactions) in users {
+        for (kind, blob) in [("daily", daily), ("pending", pending)] {
+            for (name, points, completed) in decode_legacy(blob)? {
+                conn.execute(
+                    "INSERT INTO tasks (user_id, kind, name, points) VALUES (?1, ?2, ?3, ?4)",
+                    params![user_id, kind, name, points],
+                )?;
+                if let Some(completed) = completed {
+                    conn.execute(
+                        "INSERT INTO completions (user_id, task_id, completed) VALUES (?1, ?2, ?3)",
+                        params![user_id, conn.last_insert_rowid(), completed],
+                    )?;
+                }
+            }
+        }
+
+        for (name, points, timestamp) in decode_legacy(transactions)? {
+            conn.execute(
+                "INSERT INTO transactions (user_id, name, points, timestamp) VALUES (?1, ?2, ?3, ?4)",
+                params![user_id, name, points, timestamp.unwrap_or(0)],
+            )?;
+        }
+    }
+
+    conn.execute_batch(
+        "ALTER TABLE users DROP COLUMN daily;
+        ALTER TABLE users DROP COLUMN periodic;
+        ALTER TABLE users DROP COLUMN transactions;",
+    )
+}
+
+fn events_table(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE events (
+            id                  INTEGER PRIMARY KEY,
+            user_id             INTEGER NOT NULL REFERENCES users(id),
+            actor_id            INTEGER,
+            surface             TEXT NOT NULL,
+            action              TEXT NOT NULL,
+            record_type         TEXT NOT NULL,
+            record_id           INTEGER NOT NULL,
+            name                TEXT,
+            points              INTEGER,
+            record_timestamp    INTEGER,
+            timestamp           INTEGER NOT NULL);
+
+        CREATE INDEX events_user ON events (user_id, id);
+
+        CREATE TRIGGER events_no_update BEFORE UPDATE ON events
+        BEGIN
+            SELECT RAISE(ABORT, 'events are append-only');
+        END;
+
+        CREATE TRIGGER events_no_delete BEFORE DELETE ON events
+        BEGIN
+            SELECT RAISE(ABORT, 'events are append-only');
+        END;",
+    )?;
+
+    // Seed the log with what every user already has, so replaying it rebuilds their records
+    let now = Utc::now().timestamp();
+    conn.execute(
+        "INSERT INTO events (user_id, surface, action, record_type, record_id,
+                name, points, record_timestamp, timestamp)
+            SELECT tasks.user_id, 'migration', 'insert', tasks.kind, tasks.id,
+                tasks.name, tasks.points, MAX(completions.completed), ?1
+            FROM tasks LEFT JOIN completions ON completions.task_id = tasks.id
+            GROUP BY tasks.id
+            ORDER BY tasks.id",
+        [now],
+    )?;
+    conn.execute(
+        "INSERT INTO events (user_id, surface, action, record_type, record_id,
+                name, points, record_timestamp, timestamp)
+            SELECT user_id, 'migration', 'insert', 'transaction', id, name, points, timestamp, ?1
+            FROM transactions
+            ORDER BY id",
+        [now],
+    )?;
+
+    Ok(())
+}
+
+fn trash_table(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE trash (
+            id                  INTEGER PRIMARY KEY,
+            user_id             INTEGER NOT NULL REFERENCES users(id),
+            batch               INTEGER NOT NULL,
+            action              TEXT NOT NULL,
+            record_type         TEXT NOT NULL,
+            record_id           INTEGER NOT NULL,
+            name                TEXT NOT NULL,
+            points              INTEGER NOT NULL,
+            record_timestamp    INTEGER,
+            trashed             INTEGER NOT NULL);
+
+        CREATE INDEX trash_user ON trash (user_id, batch);",
+    )
+}
+
+fn typed_records(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE tasks RENAME COLUMN name TO title;
+        ALTER TABLE tasks ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE tasks ADD COLUMN notes TEXT;
+
+        ALTER TABLE transactions RENAME COLUMN name TO reason;
+        ALTER TABLE transactions RENAME COLUMN points TO amount;
+        ALTER TABLE transactions ADD COLUMN source TEXT NOT NULL DEFAULT 'legacy';
+        ALTER TABLE transactions ADD COLUMN task_id INTEGER;
+
+        ALTER TABLE events ADD COLUMN payload TEXT;
+        ALTER TABLE trash ADD COLUMN payload TEXT;",
+    )?;
+
+    // Nobody knows when existing tasks were created, so they keep the default of 0
+    Ok(())
+}
+
+fn task_recurrence(conn: &Connection) -> Result<()> {
+    // Every existing task keeps recurring daily
+    conn.execute_batch("ALTER TABLE tasks ADD COLUMN recurrence TEXT NOT NULL DEFAULT 'daily';")
+}
+
+fn task_due_dates(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE tasks ADD COLUMN due_at INTEGER;")
+}
+
+fn task_progress(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE tasks ADD COLUMN target INTEGER;
+        ALTER TABLE tasks ADD COLUMN progress INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE tasks ADD COLUMN progress_at INTEGER;
+        ALTER TABLE tasks ADD COLUMN partial INTEGER NOT NULL DEFAULT 0;",
+    )
+}
+
+fn checklist_items(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE checklist_items (
+            task_id         INTEGER NOT NULL REFERENCES tasks(id) ON DELETE CASCADE,
+            position        INTEGER NOT NULL,
+            title           TEXT NOT NULL,
+            done_at         INTEGER,
+            PRIMARY KEY (task_id, position));",
+    )
+}
+
+fn record_tags(conn: &Connection) -> Result<()> {
+    // Comma separated, see `db::join_tags`
+    conn.execute_batch(
+        "ALTER TABLE tasks ADD COLUMN tags TEXT NOT NULL DEFAULT '';
+        ALTER TABLE transactions ADD COLUMN tags TEXT NOT NULL DEFAULT '';",
+    )
+}
+
+fn task_penalties(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE tasks ADD COLUMN penalty INTEGER NOT NULL DEFAULT 0;")
+}
+
+fn user_settings(conn: &Connection) -> Result<()> {
+    // Users without a row keep UTC days starting at midnight
+    conn.execute_batch(
+        "CREATE TABLE settings (
+            user_id         INTEGER PRIMARY KEY REFERENCES users(id),
+            utc_offset      INTEGER NOT NULL DEFAULT 0,
+            day_start       INTEGER NOT NULL DEFAULT 0);",
+    )
+}
+
+fn task_priorities(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE tasks ADD COLUMN priority TEXT NOT NULL DEFAULT 'normal';")
+}
+
+fn completion_proofs(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE completions ADD COLUMN note TEXT;
+        ALTER TABLE completions ADD COLUMN attachment TEXT;",
+    )
+}
+
+fn purchase_rewards(conn: &Connection) -> Result<()> {
+    // Purchases made so far are named `Bought: <item>` and none of them were redeemed
+    conn.execute_batch(
+        "ALTER TABLE transactions ADD COLUMN item TEXT;
+        ALTER TABLE transactions ADD COLUMN redeemed_at INTEGER;
+        UPDATE transactions SET item=substr(reason, 9)
+            WHERE source='purchase' AND reason LIKE 'Bought: %';",
+    )
+}
+
+fn user_shop_items(conn: &Connection) -> Result<()> {
+    // Restocks are stored as written, e.g. `every 3 days`, and dates as YYYY-MM-DD
+    conn.execute_batch(
+        "CREATE TABLE shop_items (
+            id              INTEGER PRIMARY KEY,
+            user_id         INTEGER NOT NULL REFERENCES users(id),
+            name            TEXT NOT NULL,
+            cost            INTEGER NOT NULL,
+            qty             INTEGER,
+            description     TEXT,
+            restock         TEXT,
+            available_from  TEXT,
+            available_until TEXT,
+            UNIQUE (user_id, name COLLATE NOCASE));",
+    )
+}
+
+fn shop_stock(conn: &Connection) -> Result<()> {
+    // Catalog items share the stock of owner 0. Purchases made so far were only counted per
+    // user, so every stock starts out full.
+    conn.execute_batch(
+        "CREATE TABLE stock (
+            owner_id        INTEGER NOT NULL,
+            item            TEXT NOT NULL COLLATE NOCASE,
+            restocked       INTEGER NOT NULL,
+            sold            INTEGER NOT NULL,
+            PRIMARY KEY (owner_id, item));",
+    )
+}
+
+fn named_timezones(conn: &Connection) -> Result<()> {
+    // Users without a named timezone keep following their fixed UTC offset
+    conn.execute_batch("ALTER TABLE settings ADD COLUMN timezone TEXT;")
+}
+
+fn trashed_completions(conn: &Connection) -> Result<()> {
+    // Tasks trashed before this lost their earlier completions when they were deleted
+    conn.execute_batch("ALTER TABLE trash ADD COLUMN completions TEXT;")
+}
+
+fn missed_through(conn: &Connection) -> Result<()> {
+    // Users are checked for yesterday only until their first check after this
+    conn.execute_batch("ALTER TABLE users ADD COLUMN missed_through TEXT;")
+}
+
+fn name_keys(conn: &Connection) -> Result<()> {
+    // `COLLATE NOCASE` only ignores the case of ASCII letters, while names are now matched by
+    // `catalog::name_key`, which lowercases every letter. Tables are rebuilt without it.
+    conn.execute_batch(
+        "ALTER TABLE shop_items RENAME TO shop_items_by_name;
+        CREATE TABLE shop_items (
+            id              INTEGER PRIMARY KEY,
+            user_id         INTEGER NOT NULL REFERENCES users(id),
+            name            TEXT NOT NULL,
+            key             TEXT NOT NULL,
+            cost            INTEGER NOT NULL,
+            qty             INTEGER,
+            description     TEXT,
+            restock         TEXT,
+            available_from  TEXT,
+            available_until TEXT,
+            UNIQUE (user_id, key));
+        INSERT INTO shop_items
+            SELECT id, user_id, name, lower(name), cost, qty, description, restock,
+                available_from, available_until
+            FROM shop_items_by_name;
+        DROP TABLE shop_items_by_name;
+
+        ALTER TABLE stock RENAME TO stock_by_name;
+        CREATE TABLE stock (
+            owner_id        INTEGER NOT NULL,
+            item            TEXT NOT NULL,
+            restocked       INTEGER NOT NULL,
+            sold            INTEGER NOT NULL,
+            PRIMARY KEY (owner_id, item));",
+    )?;
+
+    // Items whose names only differed in the case of other letters now clash, so the later
+    // one gets its id added to its name
+    let items = {
+        let mut stmt = conn.prepare("SELECT id, name FROM shop_items ORDER BY id")?;
+        let items = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        items
+    };
+    for (id, name) in items {
+        let updated = conn.execute(
+            "UPDATE OR IGNORE shop_items SET key=?2 WHERE id=?1",
+            params![id, catalog::name_key(&name)],
+        )?;
+        if updated == 0 {
+            let name = format!("{} ({})", name, id);
+            conn.execute(
+                "UPDATE shop_items SET name=?2, key=?3 WHERE id=?1",
+                params![id, name, catalog::name_key(&name)],
+            )?;
+        }
+    }
+
+    // Their stocks are merged into the first one's, and the renamed item starts out full
+    let stock = {
+        let mut stmt = conn.prepare("SELECT owner_id, item, restocked, sold FROM stock_by_name")?;
+        let stock = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        stock
+    };
+    for (owner_id, item, restocked, sold) in stock {
+        conn.execute(
+            "INSERT INTO stock (owner_id, item, restocked, sold) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT DO NOTHING",
+            params![owner_id, catalog::name_key(&item), restocked, sold],
+        )?;
+    }
+    conn.execute_batch("DROP TABLE stock_by_name;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{db::Database, events, records::Source};
+
+    #[test]
+    fn legacy_blobs_upgrade_to_the_latest_schema() {
+        let daily: Vec<LegacyRecord> = vec![
+            ("Gym".to_owned(), 10, Some(1_000)),
+            ("Read".to_owned(), 3, None),
+        ];
+        let pending: Vec<LegacyRecord> = vec![("Taxes".to_owned(), 50, None)];
+        let transactions: Vec<LegacyRecord> = vec![("Cake".to_owned(), -5, Some(2_000))];
+
+        // The schema every database had before migrations existed
+        let db = Database::open_in_memory().unwrap();
+        db.lock()
+            .execute_batch(
+                "CREATE TABLE users (
+                    id              INTEGER PRIMARY KEY,
+                    daily           BLOB,
+                    periodic        BLOB,
+                    transactions    BLOB)",
+            )
+            .unwrap();
+        db.lock()
+            .execute(
+                "INSERT INTO users (id, daily, periodic, transactions) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    7,
+                    bincode::serialize(&daily).unwrap(),
+                    bincode::serialize(&pending).unwrap(),
+                    bincode::serialize(&transactions).unwrap()
+                ],
+            )
+            .unwrap();
+
+        let latest = MIGRATIONS.last().unwrap().version;
+        assert_eq!(db.migrate().unwrap(), (0, latest));
+        let user = db.query_user(7).unwrap().unwrap();
+        let tasks = user
+            .daily
+            .iter()
+            .chain(&user.pending)
+            .map(|task| (task.title.as_str(), task.points, task.completed_at))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tasks,
+            vec![
+                ("Gym", 10, Some(1_000)),
+                ("Read", 3, None),
+                ("Taxes", 50, None)
+            ]
+        );
+        assert_eq!(user.transactions.len(), 1);
+        assert_eq!(user.transactions[0].reason, "Cake");
+        assert_eq!(user.transactions[0].amount, -5);
+        assert_eq!(user.transactions[0].timestamp, 2_000);
+        assert_eq!(user.transactions[0].source, Source::Legacy);
+        assert_eq!(user, events::replay(7, &db.query_events(7).unwrap()));
+
+        // Migrating an up to date database changes nothing
+        assert_eq!(db.migrate().unwrap(), (latest, latest));
+        assert_eq!(db.query_user(7).unwrap().unwrap(), user);
+    }
+
+    #[test]
+    fn names_only_differing_in_non_ascii_case_are_told_apart() {
+        // A database at the last schema that matched names with `COLLATE NOCASE`
+        let db = Database::open_in_memory().unwrap();
+        {
+            let conn = db.lock();
+            schema_version(&conn).unwrap();
+            for migration in MIGRATIONS.iter().filter(|migration| migration.version < 21) {
+                (migration.up)(&conn).unwrap();
+                conn.execute(
+                    "INSERT INTO schema_version (version, description, applied)
+                        VALUES (?1, ?2, 0)",
+                    params![migration.version, migration.description],
+                )
+                .unwrap();
+            }
+            conn.execute_batch(
+                "INSERT INTO users (id) VALUES (1);
+                INSERT INTO shop_items (id, user_id, name, cost) VALUES (1, 1, 'Café', 10);
+                INSERT INTO shop_items (id, user_id, name, cost) VALUES (2, 1, 'CAFÉ', 20);
+                INSERT INTO stock (owner_id, item, restocked, sold) VALUES (1, 'Café', 0, 2);
+                INSERT INTO stock (owner_id, item, restocked, sold) VALUES (1, 'CAFÉ', 0, 1);
+                INSERT INTO stock (owner_id, item, restocked, sold) VALUES (0, 'Cake', 0, 4);",
+            )
+            .unwrap();
+        }
+
+        assert_eq!(db.migrate().unwrap().1, MIGRATIONS.last().unwrap().version);
+        let items = db.query_shop_items(1).unwrap();
+        let names = items
+            .iter()
+            .map(|item| item.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Café", "CAFÉ (2)"]);
+
+        let sold = |owner: i64, item: &str| -> i64 {
+            db.lock()
+                .query_row(
+                    "SELECT sold FROM stock WHERE owner_id=?1 AND item=?2",
+                    params![owner, item],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert_eq!(sold(1, "café"), 2);
+        assert_eq!(sold(0, "cake"), 4);
+    }
+
+    #[test]
+    fn versions_are_in_order() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version as usize, index + 1);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! The audit log. Every change to a user's records is appended as an [`Event`], so a user's
+//! state can always be explained, and rebuilt, from its history.
+use super::{
+    db::{RecordType, User},
+    records::Record,
+    RecordId,
+};
+
+/// Where a change came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Surface {
+    Daily,
+    Pending,
+    Modify,
+    Undo,
+    Trash,
+    DbUtil,
+    Shop,
+    Inventory,
+    /// Penalties the bot applies on its own
+    Penalty,
+    /// Records that already existed when the audit log was introduced
+    Migration,
+}
+
+impl Surface {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Surface::Daily => "daily",
+            Surface::Pending => "pending",
+            Surface::Modify => "modify",
+            Surface::Undo => "undo",
+            Surface::Trash => "trash",
+            Surface::DbUtil => "db_util",
+            Surface::Shop => "shop",
+            Surface::Inventory => "inventory",
+            Surface::Penalty => "penalty",
+            Surface::Migration => "migration",
+        }
+    }
+}
+
+/// Who made a change, and through which surface
+#[derive(Clone, Copy, Debug)]
+pub struct Origin {
+    /// The discord user behind the change, or `None` for changes made outside of discord
+    pub actor_id: Option<u64>,
+    pub surface: Surface,
+}
+
+impl Origin {
+    /// A change made by a discord user
+    pub fn user(actor_id: u64, surface: Surface) -> Self {
+        Self {
+            actor_id: Some(actor_id),
+            surface,
+        }
+    }
+
+    /// A change made outside of discord, e.g. from `db_util`
+    pub fn system(surface: Surface) -> Self {
+        Self {
+            actor_id: None,
+            surface,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Insert,
+    Update,
+    Delete,
+    Complete,
+    /// Progress was logged towards a task's target
+    Progress,
+    /// A trashed record was put back
+    Restore,
+    /// A note or attachment was added to a task's latest completion
+    Attach,
+    /// A reward bought in the shop was used
+    Redeem,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Insert => "insert",
+            Action::Update => "update",
+            Action::Delete => "delete",
+            Action::Complete => "complete",
+            Action::Progress => "progress",
+            Action::Restore => "restore",
+            Action::Attach => "attach",
+            Action::Redeem => "redeem",
+        }
+    }
+
+    pub fn parse(action: &str) -> Option<Self> {
+        match action {
+            "insert" => Some(Action::Insert),
+            "update" => Some(Action::Update),
+            "delete" => Some(Action::Delete),
+            "complete" => Some(Action::Complete),
+            "progress" => Some(Action::Progress),
+            "restore" => Some(Action::Restore),
+            "attach" => Some(Action::Attach),
+            "redeem" => Some(Action::Redeem),
+            _ => None,
+        }
+    }
+}
+
+/// A single entry of the audit log
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub id: i64,
+    pub user_id: u64,
+    pub actor_id: Option<u64>,
+    /// Kept as text so entries written by newer surfaces still load
+    pub surface: String,
+    pub action: Action,
+    pub record_type: RecordType,
+    pub record_id: RecordId,
+    /// The record as it was after the change, `None` for deletions
+    pub record: Option<Record>,
+    pub timestamp: i64,
+}
+
+/// Rebuild a user's records by applying their events in order
+pub fn replay(user_id: u64, events: &[Event]) -> User {
+    let mut user = User::new(user_id);
+
+    for event in events.iter().filter(|event| event.user_id == user_id) {
+        match (&event.record, event.action) {
+            (_, Action::Delete) | (None, _) => {
+                user.remove_record(event.record_type, event.record_id);
+            }
+            (Some(record), action) => {
+                let mut record = record.clone();
+                if action != Action::Insert {
+                    if let Some(previous) = user.record(event.record_type, event.record_id) {
+                        record.keep_provenance(&previous);
+                    }
+                }
+                user.put_record(record);
+            }
+        }
+    }
+
+    user
+}
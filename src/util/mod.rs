@@ -1,6 +1,22 @@
+pub mod backup;
+pub mod catalog;
 pub mod components;
 pub mod db;
+pub mod events;
+pub mod penalty;
 pub mod records;
+pub mod recurrence;
+pub mod settings;
+pub mod store;
+pub mod streaks;
+pub mod trash;
 
 pub use components::{Component, ComponentManager};
-pub use records::{RecordRow, Records};
+pub use events::{Origin, Surface};
+pub use records::{
+    ChecklistItem, Completion, Priority, Proof, Record, RecordId, Reward, Source, Task, TaskKind,
+    Transaction,
+};
+pub use recurrence::{Due, Recurrence};
+pub use settings::Settings;
+pub use store::{BlockingStore, MemoryStore, UserStore};
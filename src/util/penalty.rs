@@ -0,0 +1,124 @@
+//! Points taken away for letting tasks slip
+use std::{env, sync::Arc, time::Duration};
+
+use chrono::NaiveDate;
+
+use super::{
+    records::{Source, Task, Transaction},
+    settings::Settings,
+    store::UserStore,
+    Origin, Surface,
+};
+
+/// How often to look for users whose day just rolled over, or whose tasks just went overdue
+const ROLLOVER_CHECK: Duration = Duration::from_secs(15 * 60);
+
+/// How many points each kind of slip costs, 0 to never take any
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PenaltyConfig {
+    /// Taken once when a pending task goes past its due date without being completed
+    pub overdue: i64,
+}
+
+impl PenaltyConfig {
+    /// Read the configuration from `OVERDUE_PENALTY`, defaulting to no penalties at all
+    pub fn from_env() -> Self {
+        Self {
+            overdue: env::var("OVERDUE_PENALTY").map_or(0, |penalty| {
+                penalty.parse().expect("OVERDUE_PENALTY must be an integer")
+            }),
+        }
+    }
+}
+
+/// Whether a daily task with a missed-day penalty was due in a period ending on `day` and not
+/// completed in it, given every time it was completed. Tasks are never penalized for the day
+/// they were created on.
+pub fn missed(task: &Task, completions: &[i64], day: NaiveDate, settings: &Settings) -> bool {
+    let created = settings.day_of(task.created_at);
+    let completions = completions
+        .iter()
+        .map(|timestamp| settings.day_of(*timestamp))
+        .collect::<Vec<_>>();
+    task.penalty > 0 && created < day && task.recurrence.missed(created, &completions, day)
+}
+
+/// The days that ended after `through`, the last day missed-day penalties were taken for, oldest
+/// first. Users that were never checked are only checked for yesterday.
+pub fn unchecked_days(through: Option<NaiveDate>, settings: &Settings) -> Vec<NaiveDate> {
+    let yesterday = settings.today().pred();
+    let mut day = through.map_or(yesterday, |through| through.succ());
+    let mut days = Vec::new();
+    while day <= yesterday {
+        days.push(day);
+        day = day.succ();
+    }
+    days
+}
+
+/// The penalty for missing a task on `day`, dated at the very end of that day
+pub fn missed_penalty(task: &Task, day: NaiveDate, settings: &Settings) -> Transaction {
+    let mut transaction = Transaction::new(
+        format!("Missed: {}", task.title),
+        -task.penalty,
+        Source::Missed(task.id),
+    );
+    transaction.timestamp = settings.start_of(day.succ()).timestamp() - 1;
+    transaction.tags = task.tags.clone();
+    transaction
+}
+
+/// The penalty for letting a pending task go overdue, dated when it fell due
+pub fn overdue_penalty(task: &Task, penalty: i64) -> Transaction {
+    let mut transaction = Transaction::new(
+        format!("Overdue: {}", task.title),
+        -penalty,
+        Source::Overdue(task.id),
+    );
+    if let Some(due_at) = task.due_at {
+        transaction.timestamp = due_at;
+    }
+    transaction.tags = task.tags.clone();
+    transaction
+}
+
+/// Whether a transaction is the penalty for missing the task with this id on `day`
+pub fn is_missed_penalty(
+    transaction: &Transaction,
+    task_id: i64,
+    day: NaiveDate,
+    settings: &Settings,
+) -> bool {
+    transaction.source == Source::Missed(task_id) && settings.day_of(transaction.timestamp) == day
+}
+
+/// Take the missed-day penalties of every user shortly after their day rolls over, and the
+/// overdue penalties shortly after their tasks fall due, forever
+pub async fn schedule(store: Arc<dyn UserStore>, config: PenaltyConfig) {
+    let mut interval = tokio::time::interval(ROLLOVER_CHECK);
+    loop {
+        interval.tick().await;
+
+        let store = store.clone();
+        let penalized = tokio::task::spawn_blocking(move || -> rusqlite::Result<usize> {
+            let origin = Origin::system(Surface::Penalty);
+            let mut penalized = 0;
+            for user_id in store.user_ids()? {
+                penalized += store.penalize_missed(origin, user_id)?.len();
+                if config.overdue > 0 {
+                    penalized += store
+                        .penalize_overdue(origin, user_id, config.overdue)?
+                        .len();
+                }
+            }
+            Ok(penalized)
+        })
+        .await;
+        match penalized {
+            Ok(Ok(0)) => {}
+            Ok(Ok(penalized)) => println!("Took {} penalties", penalized),
+            Ok(Err(e)) => println!("{}", e),
+            Err(e) => println!("Penalty task failed: {}", e),
+        }
+    }
+}
@@ -1,100 +1,572 @@
-use core::slice;
-use std::ops::Index;
+//! The records a user owns: tasks to complete, and the transactions that move their coins
+use std::{fmt, str::FromStr};
 
-use rusqlite::{
-    types::{FromSql, ToSqlOutput},
-    ToSql,
-};
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 
-pub type Record = (String, i64, Option<i64>);
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Records(pub Vec<Record>);
+use super::{db::RecordType, recurrence::Recurrence, settings::Settings};
 
-#[derive(Debug, Deserialize, Eq, PartialEq)]
-struct RecordWrite {
-    pub task: String,
-    pub points: i64,
-    pub completed: Option<i64>,
+/// The stable, unique id of a record. Unlike its position, it never changes.
+pub type RecordId = i64;
+
+/// The `(name, points, completed)` tuple records used to be stored as, one bincode blob per
+/// list of records. This must never change, so blobs written by older versions keep decoding.
+pub type LegacyRecord = (String, i64, Option<i64>);
+
+/// Split tags such as `health, #Work chores` into lowercase tags without duplicates
+pub fn parse_tags(input: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    for tag in input.split(|c: char| c == ',' || c.is_whitespace()) {
+        let tag = tag.trim_start_matches('#').to_lowercase();
+        if !tag.is_empty() && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
 }
 
-#[derive(Serialize)]
-pub struct RecordRow<'a> {
-    pub task: &'a str,
-    pub points: i64,
-    pub completed: Option<i64>,
+/// Decode a list of records the way older versions stored them
+pub fn decode_legacy(blob: &[u8]) -> bincode::Result<Vec<LegacyRecord>> {
+    bincode::deserialize(blob)
 }
 
-impl Records {
-    pub fn new() -> Self {
-        Records(Vec::new())
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TaskKind {
+    /// Due again every day
+    Daily,
+    /// Done once, then gone
+    Pending,
+}
+
+impl From<TaskKind> for RecordType {
+    fn from(kind: TaskKind) -> Self {
+        match kind {
+            TaskKind::Daily => RecordType::Daily,
+            TaskKind::Pending => RecordType::Pending,
+        }
     }
+}
+
+/// How urgent a task is. Pending tasks are listed most urgent first.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Urgent,
+}
 
-    pub fn push(&mut self, task: String, points: i64, timestamp: Option<i64>) {
-        self.0.push((task, points, timestamp))
+impl Priority {
+    pub const ALL: [Priority; 4] = [
+        Priority::Low,
+        Priority::Normal,
+        Priority::High,
+        Priority::Urgent,
+    ];
+
+    /// The value stored in the `priority` column of `tasks`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
     }
 
-    pub fn len(&self) -> usize {
-        self.0.len()
+    pub fn parse(priority: &str) -> Option<Self> {
+        let priority = priority.trim().to_lowercase();
+        Priority::ALL
+            .into_iter()
+            .find(|candidate| candidate.as_str() == priority)
     }
 
-    pub fn iter(&self) -> slice::Iter<'_, Record> {
-        self.0.iter()
+    /// The badge shown next to a task's title, nothing for normal tasks
+    pub fn badge(&self) -> &'static str {
+        match self {
+            Priority::Low => "🔽 ",
+            Priority::Normal => "",
+            Priority::High => "🔼 ",
+            Priority::Urgent => "⏫ ",
+        }
     }
+}
 
-    pub fn iter_mut(&mut self) -> slice::IterMut<'_, Record> {
-        self.0.iter_mut()
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
     }
 }
 
-impl ToSql for Records {
-    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
-        Ok(ToSqlOutput::from(
-            bincode::serialize(self).expect("Unable to serialize Records"),
-        ))
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(priority: &str) -> Result<Self, Self::Err> {
+        Priority::parse(priority).ok_or_else(|| format!("Invalid priority {}", priority))
     }
 }
 
-impl FromSql for Records {
-    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
-        Ok(bincode::deserialize(value.as_blob().unwrap()).unwrap())
+/// What a user attached to a completion to show what they actually did
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Proof {
+    pub note: Option<String>,
+    /// The URL of an image or file
+    pub attachment: Option<String>,
+}
+
+impl Proof {
+    pub fn new(note: Option<String>, attachment: Option<String>) -> Option<Self> {
+        let note = note
+            .map(|note| note.trim().to_owned())
+            .filter(|note| !note.is_empty());
+        (note.is_some() || attachment.is_some()).then_some(Self { note, attachment })
     }
 }
 
-impl Index<usize> for Records {
-    type Output = Record;
+impl fmt::Display for Proof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.note, &self.attachment) {
+            (Some(note), Some(attachment)) => write!(f, "{} ([attachment]({}))", note, attachment),
+            (Some(note), None) => write!(f, "{}", note),
+            (None, Some(attachment)) => write!(f, "[attachment]({})", attachment),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// One time a task was completed, with whatever was attached to show for it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Completion {
+    pub completed: i64,
+    pub proof: Option<Proof>,
+}
+
+/// One step of a task's checklist
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChecklistItem {
+    pub title: String,
+    /// When the item was checked, `None` if it still has to be done
+    pub done_at: Option<i64>,
+}
 
-    fn index(&self, index: usize) -> &Self::Output {
-        &self.0[index]
+impl ChecklistItem {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            done_at: None,
+        }
     }
 }
 
-impl IntoIterator for Records {
-    type Item = Record;
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Task {
+    pub id: RecordId,
+    pub title: String,
+    /// Coins awarded for completing the task
+    pub points: i64,
+    /// When the task was created, 0 if it predates keeping track of it
+    pub created_at: i64,
+    /// When the task was last completed, `None` if it never was
+    pub completed_at: Option<i64>,
+    pub notes: Option<String>,
+    pub kind: TaskKind,
+    /// How often the task is due again, only daily tasks recur
+    #[serde(default)]
+    pub recurrence: Recurrence,
+    /// When a pending task has to be completed by, if ever
+    #[serde(default)]
+    pub due_at: Option<i64>,
+    /// How much there is to do, e.g. 8 glasses of water. `None` for tasks that are simply
+    /// done or not.
+    #[serde(default)]
+    pub target: Option<u32>,
+    /// How much has been done towards the target
+    #[serde(default)]
+    pub progress: u32,
+    /// When progress was last logged
+    #[serde(default)]
+    pub progress_at: Option<i64>,
+    /// Pay points as progress is made instead of all of them on reaching the target
+    #[serde(default)]
+    pub partial: bool,
+    /// The steps of a pending task, in order. Checking the last one completes the task.
+    #[serde(default)]
+    pub checklist: Vec<ChecklistItem>,
+    /// Categories such as `health` or `chores`, passed on to the transactions the task pays
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Points taken for every period a daily task is not completed in, 0 for none
+    #[serde(default)]
+    pub penalty: i64,
+    #[serde(default)]
+    pub priority: Priority,
+    /// What was attached to the latest completion, if anything
+    #[serde(default)]
+    pub proof: Option<Proof>,
+}
+
+impl Task {
+    /// A task that has not been stored yet. Its id is assigned when it is inserted.
+    pub fn new(kind: TaskKind, title: impl Into<String>, points: i64) -> Self {
+        Self {
+            id: 0,
+            title: title.into(),
+            points,
+            created_at: Utc::now().timestamp(),
+            completed_at: None,
+            notes: None,
+            kind,
+            recurrence: Recurrence::Daily,
+            due_at: None,
+            target: None,
+            progress: 0,
+            progress_at: None,
+            partial: false,
+            checklist: Vec::new(),
+            tags: Vec::new(),
+            penalty: 0,
+            priority: Priority::Normal,
+            proof: None,
+        }
+    }
+
+    pub fn from_legacy(id: RecordId, kind: TaskKind, record: LegacyRecord) -> Self {
+        let (title, points, completed_at) = record;
+        Self {
+            id,
+            title,
+            points,
+            // Legacy records never knew when they were created
+            created_at: 0,
+            completed_at,
+            notes: None,
+            kind,
+            recurrence: Recurrence::Daily,
+            due_at: None,
+            target: None,
+            progress: 0,
+            progress_at: None,
+            partial: false,
+            checklist: Vec::new(),
+            tags: Vec::new(),
+            penalty: 0,
+            priority: Priority::Normal,
+            proof: None,
+        }
+    }
+
+    /// Complete the task at `timestamp`. The new completion has no proof yet.
+    pub fn complete(&mut self, timestamp: i64) {
+        self.completed_at = Some(timestamp);
+        self.proof = None;
+    }
 
-    type IntoIter = std::vec::IntoIter<Self::Item>;
+    /// Whether the task is past its due date without having been completed
+    pub fn is_overdue(&self, now: i64) -> bool {
+        self.completed_at.is_none() && self.due_at.is_some_and(|due_at| due_at < now)
+    }
+
+    /// Progress logged since `since`, anything older no longer counts
+    pub fn progress_since(&self, since: i64) -> u32 {
+        match self.progress_at {
+            Some(progress_at) if progress_at >= since => self.progress,
+            _ => 0,
+        }
+    }
+
+    /// When progress logged towards the target started counting: the start of the current
+    /// period for daily tasks, while progress on pending tasks never expires
+    pub fn counting_since(&self, settings: &Settings) -> i64 {
+        match self.kind {
+            TaskKind::Daily => {
+                let created = settings.day_of(self.created_at);
+                let start = self.recurrence.progress_start(created, settings.today());
+                settings.start_of(start).timestamp()
+            }
+            TaskKind::Pending => 0,
+        }
+    }
+
+    /// How many steps the task takes: its target, or the length of its checklist.
+    /// `None` for tasks that are simply done or not.
+    pub fn goal(&self) -> Option<u32> {
+        match self.checklist.len() {
+            0 => self.target,
+            items => Some(items as u32),
+        }
+    }
+
+    /// How many checklist items are done
+    pub fn checked(&self) -> u32 {
+        self.checklist
+            .iter()
+            .filter(|item| item.done_at.is_some())
+            .count() as u32
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+    /// Replace the checklist with items of these titles, keeping items that were already
+    /// checked checked
+    pub fn set_checklist(&mut self, titles: Vec<String>) {
+        let previous = std::mem::take(&mut self.checklist);
+        self.checklist = titles
+            .into_iter()
+            .map(|title| ChecklistItem {
+                done_at: previous
+                    .iter()
+                    .find(|item| item.title == title)
+                    .and_then(|item| item.done_at),
+                title,
+            })
+            .collect();
+    }
+
+    /// The points earned by taking the progress from `from` to `to`, out of the task's goal
+    pub fn payout(&self, from: u32, to: u32) -> i64 {
+        match self.goal() {
+            Some(target) if self.partial => {
+                let earned = |progress: u32| self.points * progress as i64 / target as i64;
+                earned(to) - earned(from)
+            }
+            Some(target) if from < target && to >= target => self.points,
+            Some(_) => 0,
+            None => self.points,
+        }
     }
 }
 
-impl<'a> IntoIterator for &'a Records {
-    type Item = &'a Record;
+/// Where the coins of a transaction came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Source {
+    /// Awarded for completing the task with this id
+    Task(RecordId),
+    /// Taken for letting the task with this id go overdue
+    Overdue(RecordId),
+    /// Paid for reaching a streak milestone on the task with this id
+    Streak(RecordId),
+    /// Taken for not completing the daily task with this id in time
+    Missed(RecordId),
+    /// Spent in the reward shop
+    Purchase,
+    /// Entered by hand, through `/modify` or `db_util`
+    Manual,
+    /// Recorded before transactions kept track of their source
+    Legacy,
+}
+
+impl Source {
+    /// The value stored in the `source` column of `transactions`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Source::Task(_) => "task",
+            Source::Overdue(_) => "overdue",
+            Source::Streak(_) => "streak",
+            Source::Missed(_) => "missed",
+            Source::Purchase => "purchase",
+            Source::Manual => "manual",
+            Source::Legacy => "legacy",
+        }
+    }
 
-    type IntoIter = slice::Iter<'a, Record>;
+    pub fn parse(source: &str, task_id: Option<RecordId>) -> Option<Self> {
+        match (source, task_id) {
+            ("task", Some(task_id)) => Some(Source::Task(task_id)),
+            ("overdue", Some(task_id)) => Some(Source::Overdue(task_id)),
+            ("streak", Some(task_id)) => Some(Source::Streak(task_id)),
+            ("missed", Some(task_id)) => Some(Source::Missed(task_id)),
+            ("purchase", _) => Some(Source::Purchase),
+            ("manual", _) => Some(Source::Manual),
+            ("legacy", _) => Some(Source::Legacy),
+            _ => None,
+        }
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.iter()
+    pub fn task_id(&self) -> Option<RecordId> {
+        match self {
+            Source::Task(task_id)
+            | Source::Overdue(task_id)
+            | Source::Streak(task_id)
+            | Source::Missed(task_id) => Some(*task_id),
+            _ => None,
+        }
     }
 }
 
-impl<'a> IntoIterator for &'a mut Records {
-    type Item = &'a mut Record;
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transaction {
+    pub id: RecordId,
+    pub reason: String,
+    /// Coins gained, or spent if negative
+    pub amount: i64,
+    pub timestamp: i64,
+    pub source: Source,
+    /// The tags of the task that paid it, or given by hand
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// What a purchase bought, `None` for anything else
+    #[serde(default)]
+    pub reward: Option<Reward>,
+}
 
-    type IntoIter = slice::IterMut<'a, Record>;
+/// A shop item a purchase bought, kept in the user's inventory until they use it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reward {
+    /// The item's name when it was bought
+    pub item: String,
+    /// When the user used it, `None` while it is still in their inventory
+    #[serde(default)]
+    pub redeemed_at: Option<i64>,
+}
+
+impl Transaction {
+    /// A transaction happening now that has not been stored yet. Its id is assigned when it
+    /// is inserted.
+    pub fn new(reason: impl Into<String>, amount: i64, source: Source) -> Self {
+        Self {
+            id: 0,
+            reason: reason.into(),
+            amount,
+            timestamp: Utc::now().timestamp(),
+            source,
+            tags: Vec::new(),
+            reward: None,
+        }
+    }
+
+    pub fn from_legacy(id: RecordId, record: LegacyRecord) -> Self {
+        let (reason, amount, timestamp) = record;
+        Self {
+            id,
+            reason,
+            amount,
+            timestamp: timestamp.unwrap_or(0),
+            source: Source::Legacy,
+            tags: Vec::new(),
+            reward: None,
+        }
+    }
+
+    /// Whether this bought a reward that is still waiting in the user's inventory
+    pub fn is_owned(&self) -> bool {
+        self.reward
+            .as_ref()
+            .is_some_and(|reward| reward.redeemed_at.is_none())
+    }
+}
+
+/// Any record, as kept in the audit log and the trash
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Record {
+    Task(Task),
+    Transaction(Transaction),
+}
+
+impl Record {
+    pub fn from_legacy(record_type: RecordType, id: RecordId, record: LegacyRecord) -> Self {
+        match record_type {
+            RecordType::Daily => Record::Task(Task::from_legacy(id, TaskKind::Daily, record)),
+            RecordType::Pending => Record::Task(Task::from_legacy(id, TaskKind::Pending, record)),
+            RecordType::Transaction => Record::Transaction(Transaction::from_legacy(id, record)),
+        }
+    }
+
+    pub fn id(&self) -> RecordId {
+        match self {
+            Record::Task(task) => task.id,
+            Record::Transaction(transaction) => transaction.id,
+        }
+    }
+
+    pub fn set_id(&mut self, id: RecordId) {
+        match self {
+            Record::Task(task) => task.id = id,
+            Record::Transaction(transaction) => transaction.id = id,
+        }
+    }
+
+    /// Carry over what updating a record never changes: when a task was created, and where
+    /// a transaction came from
+    pub fn keep_provenance(&mut self, previous: &Record) {
+        match (self, previous) {
+            (Record::Task(task), Record::Task(previous)) => task.created_at = previous.created_at,
+            (Record::Transaction(transaction), Record::Transaction(previous)) => {
+                transaction.source = previous.source
+            }
+            _ => {}
+        }
+    }
+
+    /// Keep where `current` stands: a task's completions, progress and ticked checklist items,
+    /// and whether a bought reward was used. Putting back an older version of a record only
+    /// reverts what was edited, never what was done since.
+    pub fn keep_progress(&mut self, current: &Record) {
+        match (self, current) {
+            (Record::Task(task), Record::Task(current)) => {
+                task.completed_at = current.completed_at;
+                task.proof = current.proof.clone();
+                task.progress = current.progress;
+                task.progress_at = current.progress_at;
+                for item in &mut task.checklist {
+                    item.done_at = current
+                        .checklist
+                        .iter()
+                        .find(|done| done.title == item.title)
+                        .and_then(|done| done.done_at);
+                }
+            }
+            (Record::Transaction(transaction), Record::Transaction(current)) => {
+                if let (Some(reward), Some(current)) = (&mut transaction.reward, &current.reward) {
+                    reward.redeemed_at = current.redeemed_at;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn record_type(&self) -> RecordType {
+        match self {
+            Record::Task(task) => task.kind.into(),
+            Record::Transaction(_) => RecordType::Transaction,
+        }
+    }
+
+    /// The task's title or the transaction's reason
+    pub fn name(&self) -> &str {
+        match self {
+            Record::Task(task) => &task.title,
+            Record::Transaction(transaction) => &transaction.reason,
+        }
+    }
+
+    pub fn tags(&self) -> &[String] {
+        match self {
+            Record::Task(task) => &task.tags,
+            Record::Transaction(transaction) => &transaction.tags,
+        }
+    }
+
+    pub fn set_tags(&mut self, tags: Vec<String>) {
+        match self {
+            Record::Task(task) => task.tags = tags,
+            Record::Transaction(transaction) => transaction.tags = tags,
+        }
+    }
+
+    /// The task's reward or the transaction's amount
+    pub fn points(&self) -> i64 {
+        match self {
+            Record::Task(task) => task.points,
+            Record::Transaction(transaction) => transaction.amount,
+        }
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.iter_mut()
+    /// When the task was last completed, or when the transaction happened
+    pub fn timestamp(&self) -> Option<i64> {
+        match self {
+            Record::Task(task) => task.completed_at,
+            Record::Transaction(transaction) => Some(transaction.timestamp),
+        }
     }
 }
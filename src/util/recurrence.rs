@@ -0,0 +1,261 @@
+//! When a daily task is due again once it has been completed
+use std::{fmt, str::FromStr};
+
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+
+const WEEKDAYS: [(Weekday, &str); 7] = [
+    (Weekday::Mon, "mon"),
+    (Weekday::Tue, "tue"),
+    (Weekday::Wed, "wed"),
+    (Weekday::Thu, "thu"),
+    (Weekday::Fri, "fri"),
+    (Weekday::Sat, "sat"),
+    (Weekday::Sun, "sun"),
+];
+
+/// How often a task is due. Written the same way it is typed into `/modify`, e.g. `daily`,
+/// `mon,wed,fri`, `every 3 days`, `3 per week` or `monthly 15`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Recurrence {
+    /// Due every day
+    #[default]
+    Daily,
+    /// Due on each of these days of the week
+    Weekdays(Vec<Weekday>),
+    /// Due once every this many days, counting from the day the task was created
+    EveryDays(u32),
+    /// Due this many times a week, on any days but at most once a day
+    TimesPerWeek(u32),
+    /// Due on this day of every month, or the last day of shorter months
+    Monthly(u32),
+}
+
+/// Where a task stands in the period containing today
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Due {
+    /// Not due at all today
+    Off,
+    /// Still to be completed today
+    Open { done: u32, required: u32 },
+    /// Completed for today, or for the whole period
+    Done { done: u32, required: u32 },
+}
+
+impl Recurrence {
+    pub fn parse(recurrence: &str) -> Option<Self> {
+        let recurrence = recurrence.trim().to_lowercase();
+        let words = recurrence.split_whitespace().collect::<Vec<_>>();
+        let count = |word: &str| word.parse().ok().filter(|count| *count > 0);
+
+        match words.as_slice() {
+            ["daily"] => Some(Recurrence::Daily),
+            ["every", n, "days"] => count(n).map(Recurrence::EveryDays),
+            [n, "per", "week"] => count(n).map(Recurrence::TimesPerWeek),
+            ["monthly", day] => count(day).filter(|day| *day <= 31).map(Recurrence::Monthly),
+            _ => {
+                let mut days = recurrence
+                    .split(',')
+                    .map(|day| {
+                        WEEKDAYS
+                            .iter()
+                            .find(|(_, name)| *name == day.trim())
+                            .map(|(weekday, _)| *weekday)
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                days.sort_by_key(|day| day.num_days_from_monday());
+                days.dedup();
+                Some(Recurrence::Weekdays(days))
+            }
+        }
+    }
+
+    /// How many completions the period asks for
+    pub fn required(&self) -> u32 {
+        match self {
+            Recurrence::TimesPerWeek(times) => *times,
+            _ => 1,
+        }
+    }
+
+    /// The first and last day of the period containing `today`, or `None` if the task is not
+    /// due today. `created` anchors rules that count days.
+    pub fn period(&self, created: NaiveDate, today: NaiveDate) -> Option<(NaiveDate, NaiveDate)> {
+        match self {
+            Recurrence::Daily => Some((today, today)),
+            Recurrence::Weekdays(days) => days.contains(&today.weekday()).then_some((today, today)),
+            Recurrence::EveryDays(days) => {
+                let days = *days as i64;
+                let elapsed = (today - created).num_days().max(0);
+                let start = created + Duration::days(elapsed - elapsed % days);
+                Some((start, start + Duration::days(days - 1)))
+            }
+            Recurrence::TimesPerWeek(_) => {
+                let start = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+                Some((start, start + Duration::days(6)))
+            }
+            Recurrence::Monthly(day) => {
+                // Clamp to the last day of shorter months
+                let due = (1..=*day)
+                    .rev()
+                    .find_map(|day| NaiveDate::from_ymd_opt(today.year(), today.month(), day))?;
+                (due == today).then_some((today, today))
+            }
+        }
+    }
+
+    /// The first day progress towards a target counts from. Progress counts towards the whole
+    /// period, except for tasks completed several times a week where each completion is
+    /// counted separately.
+    pub fn progress_start(&self, created: NaiveDate, today: NaiveDate) -> NaiveDate {
+        match self {
+            Recurrence::TimesPerWeek(_) => today,
+            _ => self
+                .period(created, today)
+                .map_or(today, |(start, _)| start),
+        }
+    }
+
+    /// Whether the period ending on `day` went by without the task being completed as often
+    /// as it asks. Always `false` on days that do not end a period.
+    pub fn missed(&self, created: NaiveDate, completions: &[NaiveDate], day: NaiveDate) -> bool {
+        match self.period(created, day) {
+            Some((start, end)) if end == day => {
+                let done = completions
+                    .iter()
+                    .filter(|day| (start..=end).contains(day))
+                    .count() as u32;
+                done < self.required()
+            }
+            _ => false,
+        }
+    }
+
+    /// Where a task stands today, given the days it was completed on
+    pub fn due(&self, created: NaiveDate, completions: &[NaiveDate], today: NaiveDate) -> Due {
+        let (start, end) = match self.period(created, today) {
+            Some(period) => period,
+            None => return Due::Off,
+        };
+        let done = completions
+            .iter()
+            .filter(|day| (start..=end).contains(day))
+            .count() as u32;
+        let required = self.required();
+
+        if done >= required || completions.contains(&today) {
+            Due::Done { done, required }
+        } else {
+            Due::Open { done, required }
+        }
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Recurrence::Daily => write!(f, "daily"),
+            Recurrence::Weekdays(days) => {
+                let names = days
+                    .iter()
+                    .filter_map(|day| {
+                        WEEKDAYS
+                            .iter()
+                            .find(|(weekday, _)| weekday == day)
+                            .map(|(_, name)| *name)
+                    })
+                    .collect::<Vec<_>>();
+                write!(f, "{}", names.join(","))
+            }
+            Recurrence::EveryDays(days) => write!(f, "every {} days", days),
+            Recurrence::TimesPerWeek(times) => write!(f, "{} per week", times),
+            Recurrence::Monthly(day) => write!(f, "monthly {}", day),
+        }
+    }
+}
+
+impl From<Recurrence> for String {
+    fn from(recurrence: Recurrence) -> Self {
+        recurrence.to_string()
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = String;
+
+    fn from_str(recurrence: &str) -> Result<Self, Self::Err> {
+        Recurrence::parse(recurrence).ok_or_else(|| format!("Invalid recurrence {}", recurrence))
+    }
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = String;
+
+    fn try_from(recurrence: String) -> Result<Self, Self::Error> {
+        recurrence.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_every_rule() {
+        assert_eq!(Recurrence::parse("daily"), Some(Recurrence::Daily));
+        assert_eq!(Recurrence::parse("  Daily "), Some(Recurrence::Daily));
+        assert_eq!(
+            Recurrence::parse("every 3 days"),
+            Some(Recurrence::EveryDays(3))
+        );
+        assert_eq!(
+            Recurrence::parse("2 per  week"),
+            Some(Recurrence::TimesPerWeek(2))
+        );
+        assert_eq!(
+            Recurrence::parse("monthly 31"),
+            Some(Recurrence::Monthly(31))
+        );
+        assert_eq!(
+            Recurrence::parse("fri, Mon,wed,mon"),
+            Some(Recurrence::Weekdays(vec![
+                Weekday::Mon,
+                Weekday::Wed,
+                Weekday::Fri
+            ]))
+        );
+    }
+
+    #[test]
+    fn parse_rejects_anything_else() {
+        for recurrence in [
+            "",
+            "weekly",
+            "every 0 days",
+            "every -2 days",
+            "every day",
+            "0 per week",
+            "monthly 0",
+            "monthly 32",
+            "monthly",
+            "mon,,wed",
+            "monday",
+        ] {
+            assert_eq!(Recurrence::parse(recurrence), None, "{:?}", recurrence);
+        }
+    }
+
+    #[test]
+    fn parse_reads_what_display_writes() {
+        for recurrence in [
+            Recurrence::Daily,
+            Recurrence::Weekdays(vec![Weekday::Tue, Weekday::Sun]),
+            Recurrence::EveryDays(10),
+            Recurrence::TimesPerWeek(4),
+            Recurrence::Monthly(15),
+        ] {
+            assert_eq!(Recurrence::parse(&recurrence.to_string()), Some(recurrence));
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Per-user preferences: which timezone they live in and when their day starts
+use std::fmt;
+
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// Where a user's days begin and end. Everything that resets daily, counts down to a reset or
+/// shows a date follows these.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Settings {
+    /// Minutes ahead of UTC, e.g. 420 for UTC+7, used when no named timezone was picked
+    pub utc_offset: i32,
+    /// A named timezone such as `Europe/Berlin`, which follows daylight saving time
+    pub timezone: Option<Tz>,
+    /// The hour a new day begins at, e.g. 4 for night owls
+    pub day_start: u32,
+}
+
+impl Settings {
+    /// Parse a UTC offset such as `+07:00`, `UTC+7`, `-5` or `+5:30` into minutes
+    pub fn parse_offset(offset: &str) -> Option<i32> {
+        let offset = offset.trim().to_uppercase();
+        let offset = offset
+            .strip_prefix("UTC")
+            .or_else(|| offset.strip_prefix("GMT"))
+            .unwrap_or(&offset);
+        if offset.is_empty() {
+            return Some(0);
+        }
+
+        let (sign, offset) = match (offset.strip_prefix('+'), offset.strip_prefix('-')) {
+            (Some(offset), _) => (1, offset),
+            (_, Some(offset)) => (-1, offset),
+            _ => (1, offset),
+        };
+        let (hours, minutes) = offset.split_once(':').unwrap_or((offset, "0"));
+        let (hours, minutes) = (hours.parse::<i32>().ok()?, minutes.parse::<i32>().ok()?);
+        ((0..=14).contains(&hours) && (0..60).contains(&minutes))
+            .then_some(sign * (hours * 60 + minutes))
+    }
+
+    /// Parse a timezone name such as `Europe/Berlin` or `america/new_york`
+    pub fn parse_timezone(timezone: &str) -> Option<Tz> {
+        Tz::from_str_insensitive(timezone.trim()).ok()
+    }
+
+    fn offset(&self) -> FixedOffset {
+        FixedOffset::east(self.utc_offset * 60)
+    }
+
+    /// The wall clock time at a timestamp for this user
+    fn local(&self, timestamp: i64) -> NaiveDateTime {
+        match self.timezone {
+            Some(timezone) => timezone.timestamp(timestamp, 0).naive_local(),
+            None => NaiveDateTime::from_timestamp(timestamp + self.utc_offset as i64 * 60, 0),
+        }
+    }
+
+    /// The timestamp at a wall clock time for this user. Times repeated when the clocks go back
+    /// are taken the first time round, and times skipped when they go forward an hour later.
+    fn utc(&self, local: NaiveDateTime) -> i64 {
+        let fixed = || (local - Duration::minutes(self.utc_offset as i64)).timestamp();
+        match self.timezone {
+            Some(timezone) => timezone
+                .from_local_datetime(&local)
+                .earliest()
+                .or_else(|| {
+                    timezone
+                        .from_local_datetime(&(local + Duration::hours(1)))
+                        .earliest()
+                })
+                .map_or_else(fixed, |time| time.timestamp()),
+            None => fixed(),
+        }
+    }
+
+    /// The day a timestamp falls on for this user
+    pub fn day_of(&self, timestamp: i64) -> NaiveDate {
+        (self.local(timestamp) - Duration::hours(self.day_start as i64)).date()
+    }
+
+    /// The day it is now for this user
+    pub fn today(&self) -> NaiveDate {
+        self.day_of(Utc::now().timestamp())
+    }
+
+    /// When this user's `day` begins
+    pub fn start_of(&self, day: NaiveDate) -> DateTime<Utc> {
+        Utc.timestamp(self.utc(day.and_hms(self.day_start, 0, 0)), 0)
+    }
+
+    /// A date and time this user typed, as a timestamp
+    pub fn timestamp(&self, local: NaiveDateTime) -> i64 {
+        self.utc(local)
+    }
+
+    /// Format a timestamp in this user's timezone
+    pub fn format(&self, timestamp: i64, format: &str) -> String {
+        match self.timezone {
+            Some(timezone) => timezone.timestamp(timestamp, 0).format(format).to_string(),
+            None => self
+                .offset()
+                .timestamp(timestamp, 0)
+                .format(format)
+                .to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(timezone) = self.timezone {
+            return write!(f, "{}, days start at {:02}:00", timezone, self.day_start);
+        }
+        let sign = if self.utc_offset < 0 { '-' } else { '+' };
+        let offset = self.utc_offset.abs();
+        write!(
+            f,
+            "UTC{}{:02}:{:02}, days start at {:02}:00",
+            sign,
+            offset / 60,
+            offset % 60,
+            self.day_start
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_offset_reads_every_spelling() {
+        assert_eq!(Settings::parse_offset("+07:00"), Some(7 * 60));
+        assert_eq!(Settings::parse_offset("UTC+7"), Some(7 * 60));
+        assert_eq!(Settings::parse_offset("gmt-5"), Some(-5 * 60));
+        assert_eq!(Settings::parse_offset(" -5 "), Some(-5 * 60));
+        assert_eq!(Settings::parse_offset("+5:30"), Some(5 * 60 + 30));
+        assert_eq!(Settings::parse_offset("-03:30"), Some(-(3 * 60 + 30)));
+        assert_eq!(Settings::parse_offset("14"), Some(14 * 60));
+        assert_eq!(Settings::parse_offset("UTC"), Some(0));
+        assert_eq!(Settings::parse_offset(""), Some(0));
+    }
+
+    #[test]
+    fn parse_offset_rejects_anything_else() {
+        for offset in [
+            "+15",
+            "+5:60",
+            "+5:-1",
+            "5:30:00",
+            "seven",
+            "UTC+",
+            "+-5",
+            "Europe/Berlin",
+        ] {
+            assert_eq!(Settings::parse_offset(offset), None, "{:?}", offset);
+        }
+    }
+}
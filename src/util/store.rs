@@ -0,0 +1,1722 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use chrono::{NaiveDate, Utc};
+use rusqlite::Result;
+
+use super::{
+    catalog::{self, Item, Purchase},
+    db::{Database, RecordType, User},
+    events::{Action, Event, Origin},
+    penalty,
+    records::{Completion, Proof, Record, Reward, Source, Task, TaskKind, Transaction},
+    settings::Settings,
+    streaks::{self, StreakBonuses},
+    trash::{self, Trashed},
+    RecordId,
+};
+
+/// Storage for users and their records. Commands only talk to a store, so the backend can be
+/// swapped, e.g. for an in-memory one in tests. Every method is atomic on its own, and every
+/// change is recorded in the user's audit log as coming from the given [`Origin`].
+pub trait UserStore: Send + Sync {
+    /// Fetch a user with all of their records, or `None` if they have never been seen
+    fn user(&self, user_id: u64) -> Result<Option<User>>;
+
+    /// Fetch every task of a kind for a user, creating the user if they have never been seen.
+    /// Tasks carry the timestamp of their latest completion.
+    fn tasks(&self, user_id: u64, kind: TaskKind) -> Result<Vec<Task>>;
+
+    /// Fetch every transaction of a user, oldest first, creating the user if they have never
+    /// been seen
+    fn transactions(&self, user_id: u64) -> Result<Vec<Transaction>>;
+
+    /// Every time a user completed a task, oldest first
+    fn completions(&self, user_id: u64, task_id: RecordId) -> Result<Vec<i64>>;
+
+    /// Every time a user completed a task with whatever they attached as proof, oldest first
+    fn completion_history(&self, user_id: u64, task_id: RecordId) -> Result<Vec<Completion>>;
+
+    /// Every proof a user attached, as `(task_id, completed, proof)`, oldest first
+    fn proofs(&self, user_id: u64) -> Result<Vec<(RecordId, i64, Proof)>>;
+
+    /// Every change ever made to a user's records, oldest first
+    fn history(&self, user_id: u64) -> Result<Vec<Event>>;
+
+    fn insert_user(&self, user_id: u64) -> Result<()>;
+
+    /// The ids of every user the store knows about
+    fn user_ids(&self) -> Result<Vec<u64>>;
+
+    /// A user's timezone and day start, the defaults if they never changed them
+    fn settings(&self, user_id: u64) -> Result<Settings>;
+
+    /// Replace a user's timezone and day start
+    fn update_settings(&self, user_id: u64, settings: Settings) -> Result<()>;
+
+    /// The items a user added to their own shop, in the order they were added
+    fn shop_items(&self, user_id: u64) -> Result<Vec<Item>>;
+
+    /// How many of an item were sold since it was last restocked, to anyone for catalog items
+    fn sold(&self, user_id: u64, item: &Item) -> Result<i64>;
+
+    /// Add an item to a user's own shop.
+    /// Returns `false` if they already have an item with that name, ignoring case.
+    fn insert_shop_item(&self, user_id: u64, item: &Item) -> Result<bool>;
+
+    /// Replace the item named `name` in a user's own shop, keeping what was sold of it since
+    /// the last restock.
+    /// Returns `false` if they have no such item, or another item has the new name.
+    fn update_shop_item(&self, user_id: u64, name: &str, item: &Item) -> Result<bool>;
+
+    /// Take the item named `name` out of a user's own shop, along with its stock.
+    /// Returns `false` if they have no such item.
+    fn delete_shop_item(&self, user_id: u64, name: &str) -> Result<bool>;
+
+    /// Store a new record, ignoring whatever id it has, and return its new id
+    fn insert_record(&self, origin: Origin, user_id: u64, record: Record) -> Result<RecordId>;
+
+    /// Overwrite the record with the same id, moving its previous contents to the trash.
+    /// When a task was created and where a transaction came from are kept as they were.
+    /// Returns `false` if the user has no such record.
+    fn update_record(&self, origin: Origin, user_id: u64, record: Record) -> Result<bool>;
+
+    /// Move the record with the given id to the trash.
+    /// Returns `false` if the user has no such record.
+    fn delete_record(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        record_type: RecordType,
+        id: RecordId,
+    ) -> Result<bool>;
+
+    /// Mark a task as completed now and award its points. A daily task that finished its
+    /// period also earns the streak bonus it reached, if any.
+    /// Returns the completion timestamp, or `None` if the user has no such task.
+    fn complete_task(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        bonuses: &StreakBonuses,
+    ) -> Result<Option<i64>>;
+
+    /// Log `amount` more towards a task's target and pay what that earned, forgetting progress
+    /// from a daily task's earlier periods. Reaching the target completes the task, like
+    /// [`Self::complete_task`].
+    /// Returns the task's progress, or `None` if the user has no such task.
+    fn add_progress(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        amount: u32,
+        bonuses: &StreakBonuses,
+    ) -> Result<Option<u32>>;
+
+    /// Check off an item of a task's checklist and pay what that earned. Checking the last one
+    /// completes the task, like [`Self::complete_task`].
+    /// Returns how many items are checked, or `None` if the user has no such task or item.
+    fn check_item(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        item: usize,
+        bonuses: &StreakBonuses,
+    ) -> Result<Option<u32>>;
+
+    /// Attach a note or an attachment to the completion of a task at `completed`.
+    /// Returns `false` if the user has no such task, it was completed again since, or that
+    /// completion already has proof.
+    fn attach_proof(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        completed: i64,
+        proof: Proof,
+    ) -> Result<bool>;
+
+    /// Take `penalty` points for every pending task that went overdue, once per task.
+    /// Returns the penalties taken just now.
+    fn penalize_overdue(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        penalty: i64,
+    ) -> Result<Vec<Transaction>>;
+
+    /// Take the missed-day penalty of every daily task that was not completed in a period
+    /// ending on a day that went by since the last check, once per task and day.
+    /// Returns the penalties taken just now.
+    fn penalize_missed(&self, origin: Origin, user_id: u64) -> Result<Vec<Transaction>>;
+
+    /// Buy an item with the user's coins, unless it costs more than they have
+    fn buy(&self, origin: Origin, user_id: u64, item: &Item) -> Result<Purchase>;
+
+    /// Mark a reward the user bought as used.
+    /// Returns `false` if the user has no such reward, or already redeemed it.
+    fn redeem(&self, origin: Origin, user_id: u64, id: RecordId) -> Result<bool>;
+
+    /// Everything in a user's trash that has not expired yet, newest first
+    fn trash(&self, user_id: u64) -> Result<Vec<Trashed>>;
+
+    /// Put back every record trashed by the change that made `batch`.
+    /// Returns the restored records, which is empty if the batch is gone.
+    fn restore(&self, origin: Origin, user_id: u64, batch: i64) -> Result<Vec<Trashed>>;
+
+    /// Restore whatever a user trashed most recently
+    fn undo(&self, origin: Origin, user_id: u64) -> Result<Vec<Trashed>>;
+}
+
+impl UserStore for Database {
+    fn user(&self, user_id: u64) -> Result<Option<User>> {
+        self.query_user(user_id)
+    }
+
+    fn tasks(&self, user_id: u64, kind: TaskKind) -> Result<Vec<Task>> {
+        self.query_tasks(user_id, kind)
+    }
+
+    fn transactions(&self, user_id: u64) -> Result<Vec<Transaction>> {
+        self.query_transactions(user_id)
+    }
+
+    fn completions(&self, user_id: u64, task_id: RecordId) -> Result<Vec<i64>> {
+        self.query_completions(user_id, task_id)
+    }
+
+    fn completion_history(&self, user_id: u64, task_id: RecordId) -> Result<Vec<Completion>> {
+        self.query_completion_history(user_id, task_id)
+    }
+
+    fn proofs(&self, user_id: u64) -> Result<Vec<(RecordId, i64, Proof)>> {
+        self.query_proofs(user_id)
+    }
+
+    fn history(&self, user_id: u64) -> Result<Vec<Event>> {
+        self.query_events(user_id)
+    }
+
+    fn insert_user(&self, user_id: u64) -> Result<()> {
+        Database::insert_user(self, user_id)
+    }
+
+    fn user_ids(&self) -> Result<Vec<u64>> {
+        self.query_user_ids()
+    }
+
+    fn settings(&self, user_id: u64) -> Result<Settings> {
+        self.query_settings(user_id)
+    }
+
+    fn update_settings(&self, user_id: u64, settings: Settings) -> Result<()> {
+        Database::update_settings(self, user_id, settings)
+    }
+
+    fn shop_items(&self, user_id: u64) -> Result<Vec<Item>> {
+        self.query_shop_items(user_id)
+    }
+
+    fn sold(&self, user_id: u64, item: &Item) -> Result<i64> {
+        self.query_sold(user_id, item)
+    }
+
+    fn insert_shop_item(&self, user_id: u64, item: &Item) -> Result<bool> {
+        Database::insert_shop_item(self, user_id, item)
+    }
+
+    fn update_shop_item(&self, user_id: u64, name: &str, item: &Item) -> Result<bool> {
+        Database::update_shop_item(self, user_id, name, item)
+    }
+
+    fn delete_shop_item(&self, user_id: u64, name: &str) -> Result<bool> {
+        Database::delete_shop_item(self, user_id, name)
+    }
+
+    fn insert_record(&self, origin: Origin, user_id: u64, record: Record) -> Result<RecordId> {
+        self.with_user_mut(user_id, origin, |user| user.insert_record(record))
+    }
+
+    fn update_record(&self, origin: Origin, user_id: u64, record: Record) -> Result<bool> {
+        self.with_user_mut(user_id, origin, |user| user.update_record(record))
+    }
+
+    fn delete_record(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        record_type: RecordType,
+        id: RecordId,
+    ) -> Result<bool> {
+        self.with_user_mut(user_id, origin, |user| user.delete_record(record_type, id))
+    }
+
+    fn complete_task(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        bonuses: &StreakBonuses,
+    ) -> Result<Option<i64>> {
+        self.with_user_mut(user_id, origin, |user| {
+            user.complete_task(kind, id, bonuses)
+        })
+    }
+
+    fn add_progress(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        amount: u32,
+        bonuses: &StreakBonuses,
+    ) -> Result<Option<u32>> {
+        self.with_user_mut(user_id, origin, |user| {
+            user.add_progress(kind, id, amount, bonuses)
+        })
+    }
+
+    fn check_item(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        item: usize,
+        bonuses: &StreakBonuses,
+    ) -> Result<Option<u32>> {
+        self.with_user_mut(user_id, origin, |user| {
+            user.check_item(kind, id, item, bonuses)
+        })
+    }
+
+    fn attach_proof(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        completed: i64,
+        proof: Proof,
+    ) -> Result<bool> {
+        self.with_user_mut(user_id, origin, |user| {
+            user.attach_proof(kind, id, completed, proof)
+        })
+    }
+
+    fn penalize_overdue(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        penalty: i64,
+    ) -> Result<Vec<Transaction>> {
+        self.with_user_mut(user_id, origin, |user| user.penalize_overdue(penalty))
+    }
+
+    fn penalize_missed(&self, origin: Origin, user_id: u64) -> Result<Vec<Transaction>> {
+        self.with_user_mut(user_id, origin, |user| user.penalize_missed())
+    }
+
+    fn buy(&self, origin: Origin, user_id: u64, item: &Item) -> Result<Purchase> {
+        self.with_user_mut(user_id, origin, |user| user.buy(item))
+    }
+
+    fn redeem(&self, origin: Origin, user_id: u64, id: RecordId) -> Result<bool> {
+        self.with_user_mut(user_id, origin, |user| user.redeem(id))
+    }
+
+    fn trash(&self, user_id: u64) -> Result<Vec<Trashed>> {
+        self.query_trash(user_id)
+    }
+
+    fn restore(&self, origin: Origin, user_id: u64, batch: i64) -> Result<Vec<Trashed>> {
+        self.with_user_mut(user_id, origin, |user| user.restore(batch))
+    }
+
+    fn undo(&self, origin: Origin, user_id: u64) -> Result<Vec<Trashed>> {
+        self.with_user_mut(user_id, origin, |user| user.undo())
+    }
+}
+
+/// A store whose calls wait on disk, moved off the async runtime's worker threads so the bot
+/// keeps handling other events meanwhile. Commands talk to the database through this, which
+/// needs the multi-threaded runtime; the schedules run their calls on blocking threads instead.
+pub struct BlockingStore<S>(pub S);
+
+impl<S: UserStore> UserStore for BlockingStore<S> {
+    fn user(&self, user_id: u64) -> Result<Option<User>> {
+        tokio::task::block_in_place(|| self.0.user(user_id))
+    }
+
+    fn tasks(&self, user_id: u64, kind: TaskKind) -> Result<Vec<Task>> {
+        tokio::task::block_in_place(|| self.0.tasks(user_id, kind))
+    }
+
+    fn transactions(&self, user_id: u64) -> Result<Vec<Transaction>> {
+        tokio::task::block_in_place(|| self.0.transactions(user_id))
+    }
+
+    fn completions(&self, user_id: u64, task_id: RecordId) -> Result<Vec<i64>> {
+        tokio::task::block_in_place(|| self.0.completions(user_id, task_id))
+    }
+
+    fn completion_history(&self, user_id: u64, task_id: RecordId) -> Result<Vec<Completion>> {
+        tokio::task::block_in_place(|| self.0.completion_history(user_id, task_id))
+    }
+
+    fn proofs(&self, user_id: u64) -> Result<Vec<(RecordId, i64, Proof)>> {
+        tokio::task::block_in_place(|| self.0.proofs(user_id))
+    }
+
+    fn history(&self, user_id: u64) -> Result<Vec<Event>> {
+        tokio::task::block_in_place(|| self.0.history(user_id))
+    }
+
+    fn insert_user(&self, user_id: u64) -> Result<()> {
+        tokio::task::block_in_place(|| self.0.insert_user(user_id))
+    }
+
+    fn user_ids(&self) -> Result<Vec<u64>> {
+        tokio::task::block_in_place(|| self.0.user_ids())
+    }
+
+    fn settings(&self, user_id: u64) -> Result<Settings> {
+        tokio::task::block_in_place(|| self.0.settings(user_id))
+    }
+
+    fn update_settings(&self, user_id: u64, settings: Settings) -> Result<()> {
+        tokio::task::block_in_place(|| self.0.update_settings(user_id, settings))
+    }
+
+    fn shop_items(&self, user_id: u64) -> Result<Vec<Item>> {
+        tokio::task::block_in_place(|| self.0.shop_items(user_id))
+    }
+
+    fn sold(&self, user_id: u64, item: &Item) -> Result<i64> {
+        tokio::task::block_in_place(|| self.0.sold(user_id, item))
+    }
+
+    fn insert_shop_item(&self, user_id: u64, item: &Item) -> Result<bool> {
+        tokio::task::block_in_place(|| self.0.insert_shop_item(user_id, item))
+    }
+
+    fn update_shop_item(&self, user_id: u64, name: &str, item: &Item) -> Result<bool> {
+        tokio::task::block_in_place(|| self.0.update_shop_item(user_id, name, item))
+    }
+
+    fn delete_shop_item(&self, user_id: u64, name: &str) -> Result<bool> {
+        tokio::task::block_in_place(|| self.0.delete_shop_item(user_id, name))
+    }
+
+    fn insert_record(&self, origin: Origin, user_id: u64, record: Record) -> Result<RecordId> {
+        tokio::task::block_in_place(|| self.0.insert_record(origin, user_id, record))
+    }
+
+    fn update_record(&self, origin: Origin, user_id: u64, record: Record) -> Result<bool> {
+        tokio::task::block_in_place(|| self.0.update_record(origin, user_id, record))
+    }
+
+    fn delete_record(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        record_type: RecordType,
+        id: RecordId,
+    ) -> Result<bool> {
+        tokio::task::block_in_place(|| self.0.delete_record(origin, user_id, record_type, id))
+    }
+
+    fn complete_task(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        bonuses: &StreakBonuses,
+    ) -> Result<Option<i64>> {
+        tokio::task::block_in_place(|| self.0.complete_task(origin, user_id, kind, id, bonuses))
+    }
+
+    fn add_progress(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        amount: u32,
+        bonuses: &StreakBonuses,
+    ) -> Result<Option<u32>> {
+        tokio::task::block_in_place(|| {
+            self.0
+                .add_progress(origin, user_id, kind, id, amount, bonuses)
+        })
+    }
+
+    fn check_item(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        item: usize,
+        bonuses: &StreakBonuses,
+    ) -> Result<Option<u32>> {
+        tokio::task::block_in_place(|| self.0.check_item(origin, user_id, kind, id, item, bonuses))
+    }
+
+    fn attach_proof(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        completed: i64,
+        proof: Proof,
+    ) -> Result<bool> {
+        tokio::task::block_in_place(|| {
+            self.0
+                .attach_proof(origin, user_id, kind, id, completed, proof)
+        })
+    }
+
+    fn penalize_overdue(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        penalty: i64,
+    ) -> Result<Vec<Transaction>> {
+        tokio::task::block_in_place(|| self.0.penalize_overdue(origin, user_id, penalty))
+    }
+
+    fn penalize_missed(&self, origin: Origin, user_id: u64) -> Result<Vec<Transaction>> {
+        tokio::task::block_in_place(|| self.0.penalize_missed(origin, user_id))
+    }
+
+    fn buy(&self, origin: Origin, user_id: u64, item: &Item) -> Result<Purchase> {
+        tokio::task::block_in_place(|| self.0.buy(origin, user_id, item))
+    }
+
+    fn redeem(&self, origin: Origin, user_id: u64, id: RecordId) -> Result<bool> {
+        tokio::task::block_in_place(|| self.0.redeem(origin, user_id, id))
+    }
+
+    fn trash(&self, user_id: u64) -> Result<Vec<Trashed>> {
+        tokio::task::block_in_place(|| self.0.trash(user_id))
+    }
+
+    fn restore(&self, origin: Origin, user_id: u64, batch: i64) -> Result<Vec<Trashed>> {
+        tokio::task::block_in_place(|| self.0.restore(origin, user_id, batch))
+    }
+
+    fn undo(&self, origin: Origin, user_id: u64) -> Result<Vec<Trashed>> {
+        tokio::task::block_in_place(|| self.0.undo(origin, user_id))
+    }
+}
+
+/// Everything a [`MemoryStore`] holds. It sits behind a single lock, so each method reads and
+/// changes it all at once, the way a [`Database`] transaction does.
+struct State {
+    users: HashMap<u64, User>,
+    events: Vec<Event>,
+    trash: Vec<Trashed>,
+    /// Every completion of each task, oldest first
+    completions: HashMap<RecordId, Vec<i64>>,
+    /// What was attached to completions, by task and completion
+    proofs: HashMap<(RecordId, i64), Proof>,
+    settings: HashMap<u64, Settings>,
+    /// The last day each user was checked for missed tasks
+    missed_through: HashMap<u64, NaiveDate>,
+    /// The items each user added to their own shop
+    shop_items: HashMap<u64, Vec<Item>>,
+    /// When each stock was restocked and how many were sold since, by owner and
+    /// [`catalog::name_key`]. Catalog items share the stock of owner 0.
+    stock: HashMap<(u64, String), (i64, i64)>,
+    next_id: RecordId,
+}
+
+impl State {
+    fn next_id(&mut self) -> RecordId {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// A user's records, creating the user if they have never been seen
+    fn user(&mut self, user_id: u64) -> &mut User {
+        self.users
+            .entry(user_id)
+            .or_insert_with(|| User::new(user_id))
+    }
+
+    fn settings(&self, user_id: u64) -> Settings {
+        self.settings.get(&user_id).copied().unwrap_or_default()
+    }
+
+    fn stock_key(item: &Item) -> (u64, String) {
+        (item.owner.unwrap_or(0), catalog::name_key(&item.name))
+    }
+
+    /// How many of an item were sold since it was last restocked, as a user with `settings`
+    /// sees it
+    fn sold(&self, item: &Item, settings: &Settings) -> i64 {
+        match self.stock.get(&Self::stock_key(item)) {
+            Some((restocked, sold)) if *restocked == item.restocked(settings) => *sold,
+            _ => 0,
+        }
+    }
+
+    /// Make a task's latest completion the one it carries, the way [`Database`] does
+    fn sync_completion(&mut self, record: &Record) {
+        let task = match record {
+            Record::Task(task) => task,
+            Record::Transaction(_) => return,
+        };
+        let completions = self.completions.entry(task.id).or_default();
+        match task.completed_at {
+            None => completions.clear(),
+            Some(timestamp) if completions.last() != Some(&timestamp) => {
+                completions.pop();
+                completions.push(timestamp);
+                completions.sort_unstable();
+            }
+            Some(_) => {}
+        }
+
+        self.proofs.retain(|(task_id, completed), _| {
+            *task_id != task.id || completions.contains(completed)
+        });
+        if let Some(completed) = task.completed_at {
+            match &task.proof {
+                Some(proof) => self.proofs.insert((task.id, completed), proof.clone()),
+                None => self.proofs.remove(&(task.id, completed)),
+            };
+        }
+    }
+
+    /// Keep a copy of a record that was deleted or overwritten, in a batch of its own
+    fn trash_record(
+        &mut self,
+        user_id: u64,
+        action: Action,
+        record: Record,
+        completions: Vec<Completion>,
+    ) {
+        let id = self.next_id();
+        self.trash.push(Trashed {
+            id,
+            user_id,
+            batch: id,
+            action,
+            record,
+            completions,
+            trashed: Utc::now().timestamp(),
+        });
+    }
+
+    /// Every completion of a task with whatever proof was attached to it, oldest first
+    fn completion_history(&self, task_id: RecordId) -> Vec<Completion> {
+        self.completions
+            .get(&task_id)
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .map(|completed| Completion {
+                completed: *completed,
+                proof: self.proofs.get(&(task_id, *completed)).cloned(),
+            })
+            .collect()
+    }
+
+    /// Everything in a user's trash that has not expired yet, newest first
+    fn trashed(&mut self, user_id: u64) -> Vec<Trashed> {
+        let expiry = trash::expiry();
+        self.trash.retain(|entry| entry.trashed >= expiry);
+        self.trash
+            .iter()
+            .rev()
+            .filter(|entry| entry.user_id == user_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Put back every record trashed in `batch`, the way [`Database`] does
+    fn restore(&mut self, origin: Origin, user_id: u64, batch: i64) -> Vec<Trashed> {
+        let (mut entries, kept): (Vec<Trashed>, _) = self
+            .trash
+            .drain(..)
+            .partition(|entry| entry.user_id == user_id && entry.batch == batch);
+        self.trash = kept;
+        // Newest first, so a record trashed twice ends up as it was before
+        entries.reverse();
+
+        for entry in &mut entries {
+            let user = self.user(user_id);
+            if entry.action == Action::Update {
+                if let Some(current) = user.record(entry.record.record_type(), entry.record.id()) {
+                    entry.record.keep_progress(&current);
+                }
+            }
+            user.put_record(entry.record.clone());
+            self.sync_completion(&entry.record);
+            if !entry.completions.is_empty() {
+                let task_id = entry.record.id();
+                self.completions.insert(
+                    task_id,
+                    entry
+                        .completions
+                        .iter()
+                        .map(|completion| completion.completed)
+                        .collect(),
+                );
+                for completion in &entry.completions {
+                    if let Some(proof) = &completion.proof {
+                        self.proofs
+                            .insert((task_id, completion.completed), proof.clone());
+                    }
+                }
+            }
+            self.log(
+                origin,
+                user_id,
+                Action::Restore,
+                entry.record.record_type(),
+                entry.record.id(),
+                Some(entry.record.clone()),
+            );
+        }
+        entries
+    }
+
+    /// Log progress made on a task at `timestamp`. Progress that completed the task is logged
+    /// as a completion.
+    fn log_progress(&mut self, origin: Origin, user_id: u64, task: &Task, timestamp: i64) {
+        let action = if task.completed_at == Some(timestamp) {
+            self.completions.entry(task.id).or_default().push(timestamp);
+            Action::Complete
+        } else {
+            Action::Progress
+        };
+        self.log(
+            origin,
+            user_id,
+            action,
+            task.kind.into(),
+            task.id,
+            Some(Record::Task(task.clone())),
+        );
+    }
+
+    /// Pay the points a task earned, unless it earned none
+    fn pay(&mut self, origin: Origin, user_id: u64, task: &Task, amount: i64, timestamp: i64) {
+        if amount == 0 {
+            return;
+        }
+
+        let mut transaction = Transaction::new(task.title.clone(), amount, Source::Task(task.id));
+        transaction.tags = task.tags.clone();
+        transaction.id = self.next_id();
+        transaction.timestamp = timestamp;
+        self.insert_transaction(origin, user_id, transaction);
+    }
+
+    /// Pay the streak bonus a task earned by being completed just now, if it earned one
+    fn pay_bonus(&mut self, origin: Origin, user_id: u64, task: &Task, bonuses: &StreakBonuses) {
+        if task.kind != TaskKind::Daily {
+            return;
+        }
+        let completions = self
+            .completions
+            .get(&task.id)
+            .map_or(&[][..], Vec::as_slice);
+        let transactions = &self.users[&user_id].transactions;
+        if let Some(mut transaction) = streaks::earned_bonus(
+            task,
+            completions,
+            transactions,
+            bonuses,
+            &self.settings(user_id),
+        ) {
+            transaction.id = self.next_id();
+            self.insert_transaction(origin, user_id, transaction);
+        }
+    }
+
+    /// Store a transaction the store made up itself, such as a payment or a penalty
+    fn insert_transaction(&mut self, origin: Origin, user_id: u64, transaction: Transaction) {
+        self.user(user_id)
+            .put_record(Record::Transaction(transaction.clone()));
+        self.log(
+            origin,
+            user_id,
+            Action::Insert,
+            RecordType::Transaction,
+            transaction.id,
+            Some(Record::Transaction(transaction)),
+        );
+    }
+
+    fn log(
+        &mut self,
+        origin: Origin,
+        user_id: u64,
+        action: Action,
+        record_type: RecordType,
+        record_id: RecordId,
+        record: Option<Record>,
+    ) {
+        let id = self.events.len() as i64 + 1;
+        self.events.push(Event {
+            id,
+            user_id,
+            actor_id: origin.actor_id,
+            surface: origin.surface.as_str().to_owned(),
+            action,
+            record_type,
+            record_id,
+            record,
+            timestamp: Utc::now().timestamp(),
+        });
+    }
+}
+
+/// A store that only lives as long as the process, for tests and throwaway bots
+pub struct MemoryStore(Mutex<State>);
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self(Mutex::new(State {
+            users: HashMap::new(),
+            events: Vec::new(),
+            trash: Vec::new(),
+            completions: HashMap::new(),
+            proofs: HashMap::new(),
+            settings: HashMap::new(),
+            missed_through: HashMap::new(),
+            shop_items: HashMap::new(),
+            stock: HashMap::new(),
+            next_id: 1,
+        }))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.0.lock().expect("Memory store is poisoned")
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UserStore for MemoryStore {
+    fn user(&self, user_id: u64) -> Result<Option<User>> {
+        Ok(self.lock().users.get(&user_id).cloned())
+    }
+
+    fn tasks(&self, user_id: u64, kind: TaskKind) -> Result<Vec<Task>> {
+        Ok(self.lock().user(user_id).tasks_mut(kind).clone())
+    }
+
+    fn transactions(&self, user_id: u64) -> Result<Vec<Transaction>> {
+        Ok(self.lock().user(user_id).transactions.clone())
+    }
+
+    fn completions(&self, user_id: u64, task_id: RecordId) -> Result<Vec<i64>> {
+        let state = self.lock();
+        let owned = state.users.get(&user_id).is_some_and(|user| {
+            user.record(RecordType::Daily, task_id)
+                .or_else(|| user.record(RecordType::Pending, task_id))
+                .is_some()
+        });
+        Ok(match state.completions.get(&task_id) {
+            Some(completions) if owned => completions.clone(),
+            _ => Vec::new(),
+        })
+    }
+
+    fn completion_history(&self, user_id: u64, task_id: RecordId) -> Result<Vec<Completion>> {
+        let state = self.lock();
+        let owned = state.users.get(&user_id).is_some_and(|user| {
+            user.record(RecordType::Daily, task_id)
+                .or_else(|| user.record(RecordType::Pending, task_id))
+                .is_some()
+        });
+        Ok(if owned {
+            state.completion_history(task_id)
+        } else {
+            Vec::new()
+        })
+    }
+
+    fn proofs(&self, user_id: u64) -> Result<Vec<(RecordId, i64, Proof)>> {
+        let state = self.lock();
+        let owned = state.users.get(&user_id).map_or_else(Vec::new, |user| {
+            user.daily
+                .iter()
+                .chain(&user.pending)
+                .map(|task| task.id)
+                .collect::<Vec<_>>()
+        });
+        let mut proofs = state
+            .proofs
+            .iter()
+            .filter(|((task_id, _), _)| owned.contains(task_id))
+            .map(|((task_id, completed), proof)| (*task_id, *completed, proof.clone()))
+            .collect::<Vec<_>>();
+        proofs.sort_by_key(|(task_id, completed, _)| (*completed, *task_id));
+        Ok(proofs)
+    }
+
+    fn history(&self, user_id: u64) -> Result<Vec<Event>> {
+        Ok(self
+            .lock()
+            .events
+            .iter()
+            .filter(|event| event.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    fn insert_user(&self, user_id: u64) -> Result<()> {
+        self.lock().user(user_id);
+        Ok(())
+    }
+
+    fn user_ids(&self) -> Result<Vec<u64>> {
+        let mut ids = self.lock().users.keys().copied().collect::<Vec<_>>();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    fn settings(&self, user_id: u64) -> Result<Settings> {
+        Ok(self.lock().settings(user_id))
+    }
+
+    fn update_settings(&self, user_id: u64, settings: Settings) -> Result<()> {
+        let mut state = self.lock();
+        state.user(user_id);
+        state.settings.insert(user_id, settings);
+        Ok(())
+    }
+
+    fn shop_items(&self, user_id: u64) -> Result<Vec<Item>> {
+        Ok(self
+            .lock()
+            .shop_items
+            .get(&user_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn sold(&self, user_id: u64, item: &Item) -> Result<i64> {
+        let state = self.lock();
+        Ok(state.sold(item, &state.settings(user_id)))
+    }
+
+    fn insert_shop_item(&self, user_id: u64, item: &Item) -> Result<bool> {
+        let mut state = self.lock();
+        state.user(user_id);
+        let items = state.shop_items.entry(user_id).or_default();
+        if items
+            .iter()
+            .any(|other| catalog::name_key(&other.name) == catalog::name_key(&item.name))
+        {
+            return Ok(false);
+        }
+        items.push(Item {
+            owner: Some(user_id),
+            ..item.clone()
+        });
+        Ok(true)
+    }
+
+    fn update_shop_item(&self, user_id: u64, name: &str, item: &Item) -> Result<bool> {
+        let mut state = self.lock();
+        let items = state.shop_items.entry(user_id).or_default();
+        let index = match items
+            .iter()
+            .position(|other| catalog::name_key(&other.name) == catalog::name_key(name))
+        {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        if items.iter().enumerate().any(|(other, existing)| {
+            other != index && catalog::name_key(&existing.name) == catalog::name_key(&item.name)
+        }) {
+            return Ok(false);
+        }
+        let item = Item {
+            owner: Some(user_id),
+            ..item.clone()
+        };
+        let (from, to) = (State::stock_key(&items[index]), State::stock_key(&item));
+        items[index] = item;
+        // The stock follows the item to its new name
+        if let Some(stock) = state.stock.remove(&from) {
+            state.stock.insert(to, stock);
+        }
+        Ok(true)
+    }
+
+    fn delete_shop_item(&self, user_id: u64, name: &str) -> Result<bool> {
+        let mut state = self.lock();
+        let items = state.shop_items.entry(user_id).or_default();
+        let index = match items
+            .iter()
+            .position(|item| catalog::name_key(&item.name) == catalog::name_key(name))
+        {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        let item = items.remove(index);
+        state.stock.remove(&State::stock_key(&item));
+        Ok(true)
+    }
+
+    fn insert_record(&self, origin: Origin, user_id: u64, mut record: Record) -> Result<RecordId> {
+        let mut state = self.lock();
+        let id = state.next_id();
+        record.set_id(id);
+
+        state.user(user_id).put_record(record.clone());
+        state.sync_completion(&record);
+        state.log(
+            origin,
+            user_id,
+            Action::Insert,
+            record.record_type(),
+            id,
+            Some(record),
+        );
+        Ok(id)
+    }
+
+    fn update_record(&self, origin: Origin, user_id: u64, mut record: Record) -> Result<bool> {
+        let mut state = self.lock();
+        let (record_type, id) = (record.record_type(), record.id());
+        let previous = match state.user(user_id).record(record_type, id) {
+            Some(previous) => previous,
+            None => return Ok(false),
+        };
+        record.keep_provenance(&previous);
+
+        state.user(user_id).put_record(record.clone());
+        state.sync_completion(&record);
+        state.trash_record(user_id, Action::Update, previous, Vec::new());
+        state.log(
+            origin,
+            user_id,
+            Action::Update,
+            record_type,
+            id,
+            Some(record),
+        );
+        Ok(true)
+    }
+
+    fn delete_record(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        record_type: RecordType,
+        id: RecordId,
+    ) -> Result<bool> {
+        let mut state = self.lock();
+        let previous = match state.user(user_id).remove_record(record_type, id) {
+            Some(previous) => previous,
+            None => return Ok(false),
+        };
+
+        let completions = match &previous {
+            Record::Task(task) => {
+                let completions = state.completion_history(task.id);
+                state.completions.remove(&task.id);
+                state.proofs.retain(|(task_id, _), _| *task_id != task.id);
+                completions
+            }
+            Record::Transaction(_) => Vec::new(),
+        };
+        state.trash_record(user_id, Action::Delete, previous, completions);
+        state.log(origin, user_id, Action::Delete, record_type, id, None);
+        Ok(true)
+    }
+
+    fn complete_task(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        bonuses: &StreakBonuses,
+    ) -> Result<Option<i64>> {
+        let mut state = self.lock();
+        let timestamp = Utc::now().timestamp();
+        let task = match state
+            .user(user_id)
+            .tasks_mut(kind)
+            .iter_mut()
+            .find(|task| task.id == id)
+        {
+            Some(task) => {
+                task.complete(timestamp);
+                task.clone()
+            }
+            None => return Ok(None),
+        };
+
+        state.log_progress(origin, user_id, &task, timestamp);
+        state.pay(origin, user_id, &task, task.points, timestamp);
+        state.pay_bonus(origin, user_id, &task, bonuses);
+        Ok(Some(timestamp))
+    }
+
+    fn add_progress(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        amount: u32,
+        bonuses: &StreakBonuses,
+    ) -> Result<Option<u32>> {
+        let mut state = self.lock();
+        let timestamp = Utc::now().timestamp();
+        let settings = state.settings(user_id);
+        let task = match state
+            .user(user_id)
+            .tasks_mut(kind)
+            .iter_mut()
+            .find(|task| task.id == id)
+        {
+            Some(task) => task,
+            None => return Ok(None),
+        };
+
+        let target = task.target.unwrap_or(1);
+        let from = task.progress_since(task.counting_since(&settings));
+        let to = (from + amount).min(target);
+        if to == from {
+            return Ok(Some(to));
+        }
+
+        task.progress = to;
+        task.progress_at = Some(timestamp);
+        if to == target {
+            task.complete(timestamp);
+        }
+        let task = task.clone();
+
+        state.log_progress(origin, user_id, &task, timestamp);
+        state.pay(origin, user_id, &task, task.payout(from, to), timestamp);
+        if task.completed_at == Some(timestamp) {
+            state.pay_bonus(origin, user_id, &task, bonuses);
+        }
+        Ok(Some(to))
+    }
+
+    fn check_item(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        item: usize,
+        bonuses: &StreakBonuses,
+    ) -> Result<Option<u32>> {
+        let mut state = self.lock();
+        let timestamp = Utc::now().timestamp();
+        let task = match state
+            .user(user_id)
+            .tasks_mut(kind)
+            .iter_mut()
+            .find(|task| task.id == id && item < task.checklist.len())
+        {
+            Some(task) => task,
+            None => return Ok(None),
+        };
+
+        let from = task.checked();
+        if task.checklist[item].done_at.is_some() {
+            return Ok(Some(from));
+        }
+
+        task.checklist[item].done_at = Some(timestamp);
+        let to = task.checked();
+        if to == task.checklist.len() as u32 {
+            task.complete(timestamp);
+        }
+        let task = task.clone();
+
+        state.log_progress(origin, user_id, &task, timestamp);
+        state.pay(origin, user_id, &task, task.payout(from, to), timestamp);
+        if task.completed_at == Some(timestamp) {
+            state.pay_bonus(origin, user_id, &task, bonuses);
+        }
+        Ok(Some(to))
+    }
+
+    fn attach_proof(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        kind: TaskKind,
+        id: RecordId,
+        completed: i64,
+        proof: Proof,
+    ) -> Result<bool> {
+        let mut state = self.lock();
+        let record = match state.user(user_id).tasks_mut(kind).iter_mut().find(|task| {
+            task.id == id && task.completed_at == Some(completed) && task.proof.is_none()
+        }) {
+            Some(task) => {
+                task.proof = Some(proof);
+                Record::Task(task.clone())
+            }
+            None => return Ok(false),
+        };
+
+        state.sync_completion(&record);
+        state.log(
+            origin,
+            user_id,
+            Action::Attach,
+            kind.into(),
+            id,
+            Some(record),
+        );
+        Ok(true)
+    }
+
+    fn penalize_overdue(
+        &self,
+        origin: Origin,
+        user_id: u64,
+        penalty: i64,
+    ) -> Result<Vec<Transaction>> {
+        let mut state = self.lock();
+        let now = Utc::now().timestamp();
+        let user = state.user(user_id);
+        let overdue = user
+            .pending
+            .iter()
+            .filter(|task| task.is_overdue(now))
+            .filter(|task| {
+                !user
+                    .transactions
+                    .iter()
+                    .any(|transaction| transaction.source == Source::Overdue(task.id))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut penalties = Vec::new();
+        for task in overdue {
+            let mut transaction = penalty::overdue_penalty(&task, penalty);
+            transaction.id = state.next_id();
+            state.insert_transaction(origin, user_id, transaction.clone());
+            penalties.push(transaction);
+        }
+        Ok(penalties)
+    }
+
+    fn penalize_missed(&self, origin: Origin, user_id: u64) -> Result<Vec<Transaction>> {
+        let mut state = self.lock();
+        let settings = state.settings(user_id);
+        let days = penalty::unchecked_days(state.missed_through.get(&user_id).copied(), &settings);
+        let last = match days.last() {
+            Some(last) => *last,
+            None => return Ok(Vec::new()),
+        };
+        let user = state.user(user_id).clone();
+
+        let mut missed = Vec::new();
+        for task in user.daily.iter().filter(|task| task.penalty > 0) {
+            let completions = state
+                .completions
+                .get(&task.id)
+                .map_or(&[][..], Vec::as_slice);
+            for &day in &days {
+                if !user.transactions.iter().any(|transaction| {
+                    penalty::is_missed_penalty(transaction, task.id, day, &settings)
+                }) && penalty::missed(task, completions, day, &settings)
+                {
+                    missed.push(penalty::missed_penalty(task, day, &settings));
+                }
+            }
+        }
+
+        let mut penalties = Vec::new();
+        for mut transaction in missed {
+            transaction.id = state.next_id();
+            state.insert_transaction(origin, user_id, transaction.clone());
+            penalties.push(transaction);
+        }
+        state.missed_through.insert(user_id, last);
+        Ok(penalties)
+    }
+
+    fn buy(&self, origin: Origin, user_id: u64, item: &Item) -> Result<Purchase> {
+        let mut state = self.lock();
+        let settings = state.settings(user_id);
+        let sold = state.sold(item, &settings);
+        let transactions = &state.user(user_id).transactions;
+        if let Some(refusal) = catalog::refusal(item, transactions, sold, &settings) {
+            return Ok(refusal);
+        }
+        let balance = transactions
+            .iter()
+            .map(|transaction| transaction.amount)
+            .sum::<i64>();
+        if item.qty.is_some() {
+            state.stock.insert(
+                State::stock_key(item),
+                (item.restocked(&settings), sold + 1),
+            );
+        }
+
+        let mut transaction = Transaction::new(
+            format!("Bought: {}", item.name),
+            -item.cost,
+            Source::Purchase,
+        );
+        transaction.reward = Some(Reward {
+            item: item.name.clone(),
+            redeemed_at: None,
+        });
+        transaction.id = state.next_id();
+        state.insert_transaction(origin, user_id, transaction);
+        Ok(Purchase::Bought {
+            balance: balance - item.cost,
+        })
+    }
+
+    fn redeem(&self, origin: Origin, user_id: u64, id: RecordId) -> Result<bool> {
+        let mut state = self.lock();
+        let transaction = match state
+            .user(user_id)
+            .transactions
+            .iter_mut()
+            .find(|transaction| transaction.id == id && transaction.is_owned())
+        {
+            Some(transaction) => {
+                if let Some(reward) = &mut transaction.reward {
+                    reward.redeemed_at = Some(Utc::now().timestamp());
+                }
+                transaction.clone()
+            }
+            None => return Ok(false),
+        };
+
+        state.log(
+            origin,
+            user_id,
+            Action::Redeem,
+            RecordType::Transaction,
+            id,
+            Some(Record::Transaction(transaction)),
+        );
+        Ok(true)
+    }
+
+    fn trash(&self, user_id: u64) -> Result<Vec<Trashed>> {
+        Ok(self.lock().trashed(user_id))
+    }
+
+    fn restore(&self, origin: Origin, user_id: u64, batch: i64) -> Result<Vec<Trashed>> {
+        Ok(self.lock().restore(origin, user_id, batch))
+    }
+
+    fn undo(&self, origin: Origin, user_id: u64) -> Result<Vec<Trashed>> {
+        let mut state = self.lock();
+        Ok(match state.trashed(user_id).first() {
+            Some(latest) => state.restore(origin, user_id, latest.batch),
+            None => Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::util::events::{self, Surface};
+
+    const DAY: i64 = 24 * 3600;
+
+    /// Every backend, empty and ready to use, so each scenario runs against all of them
+    pub(crate) fn stores() -> Vec<Arc<dyn UserStore>> {
+        let database = Database::open_in_memory().unwrap();
+        database.migrate().unwrap();
+        vec![Arc::new(MemoryStore::new()), Arc::new(database)]
+    }
+
+    /// The user's records agree with what replaying their history gives
+    fn assert_replays(store: &dyn UserStore, user_id: u64) {
+        let user = store.user(user_id).unwrap().unwrap();
+        assert_eq!(
+            user,
+            events::replay(user_id, &store.history(user_id).unwrap())
+        );
+    }
+
+    #[test]
+    fn completion_pays_points_once_per_completion() {
+        for store in stores() {
+            let store = store.as_ref();
+            let origin = Origin::user(1, Surface::Pending);
+            let id = store
+                .insert_record(
+                    origin,
+                    1,
+                    Record::Task(Task::new(TaskKind::Pending, "Taxes", 50)),
+                )
+                .unwrap();
+
+            let completed = store
+                .complete_task(origin, 1, TaskKind::Pending, id, &StreakBonuses::default())
+                .unwrap();
+            assert!(completed.is_some());
+            assert_eq!(store.completions(1, id).unwrap(), vec![completed.unwrap()]);
+            let transactions = store.transactions(1).unwrap();
+            assert_eq!(transactions.len(), 1);
+            assert_eq!(transactions[0].amount, 50);
+            assert_eq!(transactions[0].source, Source::Task(id));
+            assert_eq!(
+                store
+                    .complete_task(
+                        origin,
+                        1,
+                        TaskKind::Pending,
+                        id + 1,
+                        &StreakBonuses::default()
+                    )
+                    .unwrap(),
+                None
+            );
+            assert_replays(store, 1);
+        }
+    }
+
+    #[test]
+    fn modify_keeps_creation_and_trashes_previous() {
+        for store in stores() {
+            let store = store.as_ref();
+            let origin = Origin::user(1, Surface::Modify);
+            let id = store
+                .insert_record(
+                    origin,
+                    1,
+                    Record::Task(Task::new(TaskKind::Daily, "Walk", 10)),
+                )
+                .unwrap();
+            let mut task = store.tasks(1, TaskKind::Daily).unwrap()[0].clone();
+            let created_at = task.created_at;
+
+            task.title = "Run".to_owned();
+            task.created_at = 0;
+            assert!(store
+                .update_record(origin, 1, Record::Task(task.clone()))
+                .unwrap());
+            let updated = &store.tasks(1, TaskKind::Daily).unwrap()[0];
+            assert_eq!(updated.title, "Run");
+            assert_eq!(updated.created_at, created_at);
+            let trash = store.trash(1).unwrap();
+            assert_eq!(trash.len(), 1);
+            assert!(matches!(&trash[0].record, Record::Task(task) if task.title == "Walk"));
+
+            task.id = id + 1;
+            assert!(!store.update_record(origin, 1, Record::Task(task)).unwrap());
+            assert_replays(store, 1);
+        }
+    }
+
+    #[test]
+    fn undo_restores_latest_change() {
+        for store in stores() {
+            let store = store.as_ref();
+            let origin = Origin::user(1, Surface::Undo);
+            let id = store
+                .insert_record(
+                    origin,
+                    1,
+                    Record::Task(Task::new(TaskKind::Daily, "Walk", 10)),
+                )
+                .unwrap();
+            assert!(store.undo(origin, 1).unwrap().is_empty());
+
+            let mut task = store.tasks(1, TaskKind::Daily).unwrap()[0].clone();
+            task.title = "Run".to_owned();
+            store.update_record(origin, 1, Record::Task(task)).unwrap();
+            store
+                .complete_task(origin, 1, TaskKind::Daily, id, &StreakBonuses::default())
+                .unwrap();
+            assert_eq!(store.undo(origin, 1).unwrap().len(), 1);
+            let task = &store.tasks(1, TaskKind::Daily).unwrap()[0];
+            assert_eq!(task.title, "Walk");
+            assert!(task.completed_at.is_some());
+            assert_eq!(store.completions(1, id).unwrap().len(), 1);
+
+            assert!(store
+                .delete_record(origin, 1, RecordType::Daily, id)
+                .unwrap());
+            assert!(store.tasks(1, TaskKind::Daily).unwrap().is_empty());
+            assert_eq!(store.undo(origin, 1).unwrap().len(), 1);
+            assert_eq!(store.tasks(1, TaskKind::Daily).unwrap()[0].title, "Walk");
+            assert_replays(store, 1);
+        }
+    }
+
+    #[test]
+    fn streak_bonus_is_paid_with_the_completion_once() {
+        for store in stores() {
+            let store = store.as_ref();
+            let origin = Origin::user(1, Surface::Daily);
+            let bonuses = StreakBonuses {
+                milestones: vec![(2, 50)],
+            };
+            let now = Utc::now().timestamp();
+            let mut task = Task::new(TaskKind::Daily, "Gym", 10);
+            task.created_at = now - 3 * DAY;
+            task.completed_at = Some(now - DAY);
+            let id = store.insert_record(origin, 1, Record::Task(task)).unwrap();
+            let streak_bonuses = |store: &dyn UserStore| {
+                store
+                    .transactions(1)
+                    .unwrap()
+                    .into_iter()
+                    .filter(|transaction| transaction.source == Source::Streak(id))
+                    .collect::<Vec<_>>()
+            };
+
+            store
+                .complete_task(origin, 1, TaskKind::Daily, id, &bonuses)
+                .unwrap();
+            let paid = streak_bonuses(store);
+            assert_eq!(paid.len(), 1);
+            assert_eq!(paid[0].amount, 5);
+
+            store
+                .complete_task(origin, 1, TaskKind::Daily, id, &bonuses)
+                .unwrap();
+            assert_eq!(streak_bonuses(store).len(), 1);
+            assert_replays(store, 1);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn blocking_store_can_be_called_from_async_code() {
+        let store = BlockingStore(MemoryStore::new());
+        let id = store
+            .insert_record(
+                Origin::user(1, Surface::Pending),
+                1,
+                Record::Task(Task::new(TaskKind::Pending, "Taxes", 50)),
+            )
+            .unwrap();
+        let tasks = store.tasks(1, TaskKind::Pending).unwrap();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].id, id);
+    }
+
+    #[test]
+    fn missed_days_are_caught_up_once() {
+        // Both backends, last checked for missed tasks six days ago
+        let through = Settings::default().today() - chrono::Duration::days(6);
+        let memory = MemoryStore::new();
+        memory.lock().missed_through.insert(1, through);
+        let database = Database::open_in_memory().unwrap();
+        database.migrate().unwrap();
+        database
+            .lock()
+            .execute(
+                "INSERT INTO users (id, missed_through) VALUES (1, ?1)",
+                [through.to_string()],
+            )
+            .unwrap();
+        let stores: [&dyn UserStore; 2] = [&memory, &database];
+
+        for store in stores {
+            let origin = Origin::system(Surface::Penalty);
+            let now = Utc::now().timestamp();
+            let mut task = Task::new(TaskKind::Daily, "Gym", 10);
+            task.created_at = now - 10 * DAY;
+            task.completed_at = Some(now - 3 * DAY);
+            task.penalty = 5;
+            let id = store.insert_record(origin, 1, Record::Task(task)).unwrap();
+
+            // Five days went by, and the task was only done on one of them
+            let penalties = store.penalize_missed(origin, 1).unwrap();
+            assert_eq!(penalties.len(), 4);
+            assert!(penalties
+                .iter()
+                .all(|penalty| penalty.source == Source::Missed(id) && penalty.amount == -5));
+            assert!(store.penalize_missed(origin, 1).unwrap().is_empty());
+
+            // Moving the day back does not check any day a second time
+            store
+                .update_settings(
+                    1,
+                    Settings {
+                        utc_offset: -12 * 60,
+                        ..Settings::default()
+                    },
+                )
+                .unwrap();
+            assert!(store.penalize_missed(origin, 1).unwrap().is_empty());
+            assert_replays(store, 1);
+        }
+    }
+
+    #[test]
+    fn shop_items_keep_their_stock_when_renamed() {
+        for store in stores() {
+            let store = store.as_ref();
+            let origin = Origin::user(1, Surface::Shop);
+            let mut item = Item {
+                name: "Cake".to_owned(),
+                cost: 10,
+                qty: Some(3),
+                description: None,
+                restock: None,
+                available_from: None,
+                available_until: None,
+                owner: Some(1),
+            };
+            assert!(store.insert_shop_item(1, &item).unwrap());
+            assert!(!store
+                .insert_shop_item(
+                    1,
+                    &Item {
+                        name: "CAKE".to_owned(),
+                        ..item.clone()
+                    }
+                )
+                .unwrap());
+            store
+                .insert_record(
+                    origin,
+                    1,
+                    Record::Transaction(Transaction::new("Gift", 100, Source::Manual)),
+                )
+                .unwrap();
+            assert!(matches!(
+                store.buy(origin, 1, &item).unwrap(),
+                Purchase::Bought { .. }
+            ));
+
+            item.name = "Cheesecake".to_owned();
+            assert!(store.update_shop_item(1, "cake", &item).unwrap());
+            assert_eq!(store.sold(1, &item).unwrap(), 1);
+
+            // Taken out and added again, it starts with a full stock
+            assert!(store.delete_shop_item(1, "cheesecake").unwrap());
+            assert!(store.insert_shop_item(1, &item).unwrap());
+            assert_eq!(store.sold(1, &item).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn shop_item_names_ignore_case_the_same_way_everywhere() {
+        for store in stores() {
+            let store = store.as_ref();
+            let item = Item {
+                name: "Crème brûlée".to_owned(),
+                cost: 10,
+                qty: Some(2),
+                description: None,
+                restock: None,
+                available_from: None,
+                available_until: None,
+                owner: Some(1),
+            };
+            let shouted = Item {
+                name: "CRÈME BRÛLÉE".to_owned(),
+                ..item.clone()
+            };
+            assert!(store.insert_shop_item(1, &item).unwrap());
+            assert!(!store.insert_shop_item(1, &shouted).unwrap());
+
+            let origin = Origin::user(1, Surface::Shop);
+            store
+                .insert_record(
+                    origin,
+                    1,
+                    Record::Transaction(Transaction::new("Gift", 100, Source::Manual)),
+                )
+                .unwrap();
+            store.buy(origin, 1, &shouted).unwrap();
+            assert_eq!(store.sold(1, &item).unwrap(), 1);
+            assert!(store.delete_shop_item(1, " crème BRÛLÉE").unwrap());
+            assert!(store.shop_items(1).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn undoing_a_delete_keeps_every_completion() {
+        for store in stores() {
+            let store = store.as_ref();
+            let origin = Origin::user(1, Surface::Undo);
+            let settings = Settings::default();
+            let now = Utc::now().timestamp();
+            let mut task = Task::new(TaskKind::Daily, "Gym", 10);
+            task.created_at = now - 3 * DAY;
+            task.completed_at = Some(now - DAY);
+            let id = store.insert_record(origin, 1, Record::Task(task)).unwrap();
+            let proof = Proof::new(Some("leg day".to_owned()), None).unwrap();
+            assert!(store
+                .attach_proof(origin, 1, TaskKind::Daily, id, now - DAY, proof.clone())
+                .unwrap());
+            store
+                .complete_task(origin, 1, TaskKind::Daily, id, &StreakBonuses::default())
+                .unwrap();
+            let streak = |store: &dyn UserStore| {
+                let task = store.tasks(1, TaskKind::Daily).unwrap().remove(0);
+                let days = store
+                    .completions(1, task.id)
+                    .unwrap()
+                    .into_iter()
+                    .map(|completed| settings.day_of(completed))
+                    .collect::<Vec<_>>();
+                streaks::streak(
+                    &task.recurrence,
+                    settings.day_of(task.created_at),
+                    &days,
+                    settings.today(),
+                )
+            };
+            let completions = store.completions(1, id).unwrap();
+            let proofs = store.proofs(1).unwrap();
+            let history = store.completion_history(1, id).unwrap();
+            assert_eq!(completions.len(), 2);
+            assert_eq!(proofs.len(), 1);
+            assert_eq!(history[0].proof, Some(proof));
+            assert_eq!(history[1].proof, None);
+            assert!(store.completion_history(2, id).unwrap().is_empty());
+            assert_eq!(streak(store).current, 2);
+
+            assert!(store
+                .delete_record(origin, 1, RecordType::Daily, id)
+                .unwrap());
+            assert!(store.completions(1, id).unwrap().is_empty());
+            assert_eq!(store.undo(origin, 1).unwrap().len(), 1);
+
+            assert_eq!(store.completions(1, id).unwrap(), completions);
+            assert_eq!(store.proofs(1).unwrap(), proofs);
+            assert_eq!(store.completion_history(1, id).unwrap(), history);
+            assert_eq!(streak(store).current, 2);
+            assert_replays(store, 1);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! How many periods in a row a daily task has been completed, and the bonuses paid for it
+use std::env;
+
+use chrono::NaiveDate;
+
+use super::{
+    records::{Source, Task, Transaction},
+    recurrence::Recurrence,
+    settings::Settings,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Streak {
+    /// Periods in a row up to now, counting today's only once it is done
+    pub current: u32,
+    /// The longest streak ever
+    pub best: u32,
+}
+
+/// Work out a task's streak from the days it was completed on. A period only counts once the
+/// task has been completed as often as its rule asks, and today's period does not break the
+/// streak while it is still open.
+pub fn streak(
+    recurrence: &Recurrence,
+    created: NaiveDate,
+    completions: &[NaiveDate],
+    today: NaiveDate,
+) -> Streak {
+    let first = match completions.iter().min() {
+        Some(first) => *first,
+        None => return Streak::default(),
+    };
+
+    let mut periods = Vec::new();
+    let mut day = first;
+    while day <= today {
+        if let Some(period) = recurrence.period(created, day) {
+            if periods.last() != Some(&period) {
+                periods.push(period);
+            }
+        }
+        day = day.succ();
+    }
+
+    let mut done = periods
+        .iter()
+        .map(|(start, end)| {
+            completions
+                .iter()
+                .filter(|day| (start..=end).contains(day))
+                .count() as u32
+                >= recurrence.required()
+        })
+        .collect::<Vec<_>>();
+    if let (Some((_, end)), Some(false)) = (periods.last(), done.last()) {
+        if *end >= today {
+            done.pop();
+        }
+    }
+
+    let mut streak = Streak::default();
+    for done in done {
+        streak.current = if done { streak.current + 1 } else { 0 };
+        streak.best = streak.best.max(streak.current);
+    }
+    streak
+}
+
+/// Bonuses paid when a streak reaches a milestone, as a percentage of the task's points
+#[derive(Clone, Debug, Default)]
+pub struct StreakBonuses {
+    /// `(streak, percent)` pairs
+    pub milestones: Vec<(u32, i64)>,
+}
+
+impl StreakBonuses {
+    /// Read the milestones from `STREAK_BONUSES`, a comma separated list of `streak:percent`
+    /// such as `7:50,30:100`. No bonuses are paid by default.
+    pub fn from_env() -> Self {
+        let milestones = env::var("STREAK_BONUSES").map_or_else(
+            |_| Vec::new(),
+            |milestones| {
+                milestones
+                    .split(',')
+                    .filter(|milestone| !milestone.trim().is_empty())
+                    .map(|milestone| {
+                        milestone
+                            .trim()
+                            .split_once(':')
+                            .and_then(|(streak, percent)| {
+                                Some((streak.parse().ok()?, percent.parse().ok()?))
+                            })
+                            .expect("STREAK_BONUSES must look like 7:50,30:100")
+                    })
+                    .collect()
+            },
+        );
+        Self { milestones }
+    }
+
+    /// The bonus for a task worth `points` whose streak just reached `streak`, if that is a
+    /// milestone
+    pub fn bonus(&self, streak: u32, points: i64) -> Option<i64> {
+        self.milestones
+            .iter()
+            .find(|(milestone, _)| *milestone == streak)
+            .map(|(_, percent)| points * percent / 100)
+            .filter(|bonus| *bonus > 0)
+    }
+}
+
+/// The bonus a daily task earned with its latest completion, the last of `completions`, if that
+/// finished its period and brought its streak to a milestone. Nothing is paid twice in a period.
+pub fn earned_bonus(
+    task: &Task,
+    completions: &[i64],
+    transactions: &[Transaction],
+    bonuses: &StreakBonuses,
+    settings: &Settings,
+) -> Option<Transaction> {
+    let today = settings.today();
+    let created = settings.day_of(task.created_at);
+    let completions = completions
+        .iter()
+        .map(|timestamp| settings.day_of(*timestamp))
+        .collect::<Vec<_>>();
+    let (start, end) = task.recurrence.period(created, today)?;
+    let done = |completions: &[NaiveDate]| {
+        completions
+            .iter()
+            .filter(|day| (start..=end).contains(day))
+            .count() as u32
+            >= task.recurrence.required()
+    };
+    let (_, before) = completions.split_last()?;
+    if done(before) || !done(&completions) {
+        return None;
+    }
+
+    let since = settings.start_of(start).timestamp();
+    if transactions.iter().any(|transaction| {
+        transaction.source == Source::Streak(task.id) && transaction.timestamp >= since
+    }) {
+        return None;
+    }
+
+    let current = streak(&task.recurrence, created, &completions, today).current;
+    let mut transaction = Transaction::new(
+        format!("{} streak: {}", current, task.title),
+        bonuses.bonus(current, task.points)?,
+        Source::Streak(task.id),
+    );
+    transaction.tags = task.tags.clone();
+    Some(transaction)
+}
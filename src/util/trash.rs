@@ -0,0 +1,32 @@
+//! Deleted and overwritten records are kept in a per-user trash for a while, so mistakes can
+//! be undone. Everything trashed by a single change shares a batch and is restored together.
+use chrono::Utc;
+
+use super::{
+    events::Action,
+    records::{Completion, Record},
+};
+
+/// How long trashed records are kept before they are gone for good
+pub const RETENTION_DAYS: i64 = 30;
+
+/// A record as it was before it was deleted or overwritten
+#[derive(Clone, Debug)]
+pub struct Trashed {
+    pub id: i64,
+    pub user_id: u64,
+    /// Every record trashed by the same change shares a batch
+    pub batch: i64,
+    /// [`Action::Delete`] or [`Action::Update`], whichever change trashed the record
+    pub action: Action,
+    pub record: Record,
+    /// Every completion of a deleted task, oldest first, so undoing the delete keeps its
+    /// streak and proofs
+    pub completions: Vec<Completion>,
+    pub trashed: i64,
+}
+
+/// Anything trashed before this timestamp has expired
+pub fn expiry() -> i64 {
+    Utc::now().timestamp() - RETENTION_DAYS * 24 * 3600
+}